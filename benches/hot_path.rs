@@ -0,0 +1,191 @@
+//! Benchmarks for the per-record hot path.
+//!
+//! With the release profile, the `#[inline(always)]` annotations and the
+//! monomorphized record loop measured neutral here (within noise, ~340 MiB/s
+//! single-threaded either way), as the optimizer already inlined these calls
+//! within the crate. They are kept so the hot path stays inlined across the
+//! crate boundary and at lower optimization levels.
+//!
+//! Scanning records with slice iterators instead of indexing, which drops the
+//! per-byte bounds checks without any `unsafe`, was also neutral (~350 MiB/s);
+//! the loop is dominated by hashing station names.
+//!
+//! Accumulating runs of the same station outside the map took the one-station
+//! file from ~490 to ~860 MiB/s, at the cost of ~5% on interleaved stations
+//! (~440 to ~417 MiB/s) for comparing each name with the previous one. On
+//! generated files it took sorted rows from ~520 to ~780 MiB/s and shuffled
+//! rows from ~411 to ~386 MiB/s.
+//!
+//! Rounding partition splits up to cache-line or page boundaries measured
+//! neutral on 10M shuffled rows across four threads (~193, ~197 and ~183
+//! MiB/s unaligned, cache-line and page aligned, all within noise): each
+//! thread straddles at most one extra line or page, against the millions it
+//! reads in sequence. `--partition-align` stays off by default.
+//!
+//! Parsing a value as one zero-padded `u64` and combining its digits with two
+//! multiplies took `parse_measurement` from ~29 to ~23 ns per four values,
+//! and `thread_runner` from ~208 to ~217 MiB/s. Filling the word with a
+//! variable-length `copy_from_slice` instead was ~77 ns, as the copy became a
+//! `memcpy` call, so it is built from two overlapping fixed-size loads.
+//!
+//! `--assert-sorted-input`, which inserts each station once its run ends
+//! instead of merging runs into the map, measured neutral on sorted rows
+//! (~421 against ~413 MiB/s): the default loop already looks each run up
+//! only once, so both hash once per run.
+//!
+//! Storing readings as `i32` rather than `i16` (`cargo bench --features
+//! wide-measurements`) measured neutral: `parse_measurement` ~30 against ~26
+//! ns, and `thread_runner` ~179-211 against ~186-206 MiB/s on interleaved
+//! rows and ~171-209 against ~169-178 MiB/s on shuffled rows, with the
+//! spread between runs wider than the gap. The station map stays small
+//! either way, so `i16` stays the default.
+//!
+//! Finding the distinct stations in a first pass (`--two-pass`) to size the
+//! map exactly doesn't pay off: on 1M rows it ran ~108-162 MiB/s with 400
+//! stations and ~134-163 MiB/s with 10,000, against ~195-219 and ~172-194
+//! growing the map and ~202-224 and ~230 presizing it for 10,000 from the
+//! line count. The first pass hashes every name, which costs about as much
+//! as the aggregation it saves the map growth for, while `--presize` only
+//! counts newlines.
+//!
+//! Keying a map by `StationKey`, which owns its name inline, built ~19 M
+//! rows/s against ~15-17 M for a `Vec<u8>` allocated per row and ~24-30 M for
+//! names borrowed from the input as `&[u8]`. Filling and moving each 104-byte
+//! key costs most of what the allocation did, so the workers keep borrowing
+//! their keys.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rs_1brc::{aggregate, generate, parse_measurement, thread_runner, try_partition_slice_aligned, Align, Config,
+              GenerateArgs, RecordIter, StationKey, StationMap};
+
+const NUM_ROWS: usize = 1_000_000;
+const NUM_STATIONS: u64 = 400;
+
+/// Build an in-memory measurements file with a fixed pseudo-random layout so
+/// runs are comparable.
+fn measurements(num_stations: u64) -> Vec<u8> {
+    let mut state: u64 = 0x1b7c;
+    let mut next = || {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        state >> 33
+    };
+    let mut data = Vec::with_capacity(NUM_ROWS * 16);
+    for _ in 0..NUM_ROWS {
+        let station = next() % num_stations;
+        let value = (next() % 1999) as i64 - 999;
+        let sign = if value < 0 { "-" } else { "" };
+        let value = value.abs();
+        data.extend_from_slice(format!("Station{};{}{}.{}\n", station, sign, value / 10, value % 10).as_bytes());
+    }
+    data
+}
+
+fn bench_parse_measurement(c: &mut Criterion) {
+    let values: [&[u8]; 4] = [b"1.2", b"-12.3", b"99.9", b"-0.5"];
+    c.bench_function("parse_measurement", |b| {
+        b.iter(|| {
+            for value in values {
+                black_box(parse_measurement(black_box(value)));
+            }
+        })
+    });
+}
+
+fn bench_thread_runner(c: &mut Criterion) {
+    let config = Config::default();
+    let mut group = c.benchmark_group("thread_runner");
+    group.sample_size(20);
+    for (name, num_stations) in [("1M rows", NUM_STATIONS), ("1M rows, one station", 1)] {
+        let data = measurements(num_stations);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(name, |b| b.iter(|| thread_runner(black_box(&data), &config).unwrap()));
+    }
+    // The same rows grouped by station, as in a sorted file, and shuffled
+    for (name, shuffle) in [("1M rows, sorted", false), ("1M rows, shuffled", true)] {
+        let args = GenerateArgs { rows: NUM_ROWS as u64, stations: NUM_STATIONS as usize, seed: 1, shuffle };
+        let mut data = Vec::new();
+        generate(&args, &mut data).unwrap();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(name, |b| b.iter(|| thread_runner(black_box(&data), &config).unwrap()));
+        if !shuffle {
+            let config = Config { assert_sorted_input: true, ..Config::default() };
+            group.bench_function("1M rows, sorted, asserted", |b| {
+                b.iter(|| thread_runner(black_box(&data), &config).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_partition_align(c: &mut Criterion) {
+    let config = Config::default();
+    let args = GenerateArgs { rows: 10 * NUM_ROWS as u64, stations: NUM_STATIONS as usize, seed: 1, shuffle: true };
+    let mut data = Vec::new();
+    generate(&args, &mut data).unwrap();
+    let mut group = c.benchmark_group("partition_align");
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    for (name, align) in [("10M rows, unaligned", Align::None), ("10M rows, cache line", Align::CacheLine),
+                          ("10M rows, page", Align::Page)] {
+        let partitions = try_partition_slice_aligned(&data, config.threads, usize::MAX, align.bytes()).unwrap();
+        group.bench_function(name, |b| b.iter(|| aggregate(black_box(&partitions), &config).unwrap()));
+    }
+    group.finish();
+}
+
+fn bench_map_sizing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("map_sizing");
+    group.sample_size(20);
+    for num_stations in [NUM_STATIONS, 10_000] {
+        let data = measurements(num_stations);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        // With a million lines --presize sizes the map for --max-stations,
+        // which is 10,000
+        for (name, config) in [("growing", Config::default()), ("presized", Config { presize: true, ..Config::default() }),
+                               ("two-pass", Config { two_pass: true, ..Config::default() })] {
+            let name = format!("1M rows, {} stations, {}", num_stations, name);
+            group.bench_function(name, |b| b.iter(|| thread_runner(black_box(&data), &config).unwrap()));
+        }
+    }
+    group.finish();
+}
+
+fn bench_station_key(c: &mut Criterion) {
+    let data = measurements(NUM_STATIONS);
+    let records: Vec<_> = RecordIter::new(&data).map(Result::unwrap).collect();
+    let mut group = c.benchmark_group("station_key");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(records.len() as u64));
+    group.bench_function("1M rows, &[u8]", |b| {
+        b.iter(|| {
+            let mut stations = StationMap::default();
+            for &(name, value) in black_box(&records) {
+                stations.entry(name).or_default().add_measurement(value);
+            }
+            stations
+        })
+    });
+    group.bench_function("1M rows, Vec<u8>", |b| {
+        b.iter(|| {
+            let mut stations = StationMap::default();
+            for &(name, value) in black_box(&records) {
+                stations.entry(name.to_vec()).or_default().add_measurement(value);
+            }
+            stations
+        })
+    });
+    group.bench_function("1M rows, StationKey", |b| {
+        b.iter(|| {
+            let mut stations = StationMap::default();
+            for &(name, value) in black_box(&records) {
+                stations.entry(StationKey::new(name).unwrap()).or_default().add_measurement(value);
+            }
+            stations
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse_measurement, bench_thread_runner, bench_partition_align, bench_map_sizing,
+                 bench_station_key);
+criterion_main!(benches);
@@ -0,0 +1,245 @@
+use crate::{
+    check_station_count, finish_aggregation, http, longest_name, map_bytes, merge_partial, merge_stations,
+    normalize_names, record_prefix, records::next_record_start, run_partition, state, thread_cpu_time, Aggregation,
+    Align, Backend, Config, Contents, RunContext, Skipped, StationHasher, StationMap, ThreadStats,
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
+};
+
+/// Size of the chunks the input is split into, unless `--chunk-size` says
+/// otherwise. A checkpoint only ever advances by whole chunks.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// A chunk's stations, with the number of readings and the lines skipped.
+type ChunkResult<'a> = Result<(StationMap<&'a [u8]>, usize, Skipped)>;
+
+/// Aggregate a file from byte `start` on, the length of input the `resumed`
+/// stations already cover, saving checkpoints under `--checkpoint-interval`.
+///
+/// Only a single uncompressed local file read into memory is supported, as
+/// the checkpoints record a position in it. It is split into chunks of
+/// `--chunk-size`, so the flags for shaping or checking partitions are
+/// refused rather than ignored.
+pub fn aggregate_file_checkpointed(path: &str, start: usize, resumed: &StationMap<Vec<u8>>, config: &Config,
+                                   context: &RunContext) -> Result<Aggregation> {
+    let partition_flags = [
+        (config.partitions.is_some(), "--partitions"),
+        (config.print_partitions, "--print-partitions"),
+        (config.partition_align != Align::None, "--partition-align"),
+        (config.assert_sorted_input, "--assert-sorted-input"),
+    ];
+    if let Some((_, flag)) = partition_flags.iter().find(|(set, _)| *set) {
+        bail!("{} doesn't apply to checkpointed runs, which split the input into --chunk-size chunks", flag);
+    }
+    if Path::new(path).is_dir() || http::is_url(path) || path.ends_with(".zst")
+        || config.backend == Backend::Stream || config.memory_limit.is_some()
+    {
+        bail!("Checkpoints need a single uncompressed local file read into memory, not {}", path);
+    }
+    let started = Instant::now();
+    let file = File::open(path).with_context(|| format!("Failed to open file: {}", path))?;
+    let contents = Contents::load(file, config)?;
+    let data = match config.first_n_bytes {
+        Some(limit) => record_prefix(contents.as_slice(), limit),
+        None => contents.as_slice(),
+    };
+    let aggregation = aggregate_checkpointed(data, start, resumed, config, context)?;
+    finish_aggregation(aggregation, started, config)
+}
+
+/// Aggregate `data` from byte `start` on, saving checkpoints as it goes.
+///
+/// A checkpoint has to cover a prefix of the input, so `data` is split into
+/// chunks that the workers claim in order. They send each chunk's stations
+/// back to this thread, which folds them in input order, holding back any
+/// that finish ahead of an earlier chunk. Once per `--checkpoint-interval`
+/// that it has folded more, it saves `resumed` with the stations folded so
+/// far and the offset they reach. The workers never wait for a checkpoint to
+/// be written, only on the channel should this thread fall behind.
+///
+/// The returned stations are this run's alone, as for
+/// [`aggregate`](crate::aggregate).
+pub fn aggregate_checkpointed(data: &[u8], start: usize, resumed: &StationMap<Vec<u8>>, config: &Config,
+                              context: &RunContext) -> Result<Aggregation> {
+    let chunk_size = config.chunk_size.map_or(CHUNK_SIZE, |mib| mib << 20);
+    aggregate_checkpointed_with(data, start, resumed, config, context, chunk_size)
+}
+
+fn aggregate_checkpointed_with(data: &[u8], start: usize, resumed: &StationMap<Vec<u8>>, config: &Config,
+                               context: &RunContext, chunk_size: usize) -> Result<Aggregation> {
+    if start > data.len() {
+        bail!("Checkpoint covers {} bytes but the input is only {} bytes", start, data.len());
+    }
+    let mut bounds = vec![start];
+    let mut end = start;
+    while end < data.len() {
+        end = next_record_start(data, end + chunk_size);
+        bounds.push(end);
+    }
+    let chunks = bounds.len() - 1;
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = channel();
+
+    let (folded, results) = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..config.threads)
+            .map(|thread| {
+                let (next, bounds, sender) = (&next, &bounds, sender.clone());
+                scope.spawn(move || {
+                    let mut stats = ThreadStats::new(thread);
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= chunks {
+                            break;
+                        }
+                        let (from, to) = (bounds[i], bounds[i + 1]);
+                        let result = run_partition(&data[from..to], from, config, context);
+                        match &result {
+                            Ok((partial, readings, _)) => {
+                                stats.add_chunk(from, to - from, *readings);
+                                stats.longest_name = stats.longest_name.max(longest_name(partial.keys()));
+                            }
+                            // Stop the other workers claiming more chunks
+                            Err(_) => next.store(chunks, Ordering::Relaxed),
+                        }
+                        let failed = result.is_err();
+                        if sender.send((i, result)).is_err() || failed {
+                            break;
+                        }
+                    }
+                    (stats, thread_cpu_time())
+                })
+            })
+            .collect();
+        drop(sender);
+        let folded = fold_in_order(receiver, &bounds, resumed, config);
+        let results: Vec<_> = handles.into_iter().map(|handle| handle.join().expect("worker thread panicked")).collect();
+        (folded, results)
+    });
+
+    let (stations, skipped) = folded?;
+    let (threads, cpu_times): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+    let cpu_times = cpu_times.into_iter().collect::<Option<_>>().unwrap_or_default();
+    let partial_map_bytes = map_bytes(&stations);
+    Ok(Aggregation { stations, threads, skipped, partial_map_bytes, cpu_times })
+}
+
+/// Fold the chunks' results in input order as they arrive, checkpointing
+/// along the way and once more at the end.
+fn fold_in_order(receiver: Receiver<(usize, ChunkResult)>, bounds: &[usize], resumed: &StationMap<Vec<u8>>,
+                 config: &Config) -> Result<(StationMap<Vec<u8>>, Skipped)> {
+    let checkpoint = config.checkpoint_file.as_deref().zip(config.checkpoint_interval);
+    let mut stations = StationMap::with_hasher(StationHasher::from_config(config));
+    let mut skipped = Skipped::default();
+    let mut early = BTreeMap::new();
+    // Chunks folded in so far, and how many of them the last checkpoint has
+    let (mut folded, mut saved) = (0, 0);
+    let mut due = checkpoint.map(|(_, interval)| Instant::now() + interval);
+    loop {
+        // With nothing new to save there's no need to wake for a checkpoint
+        let wait = match due {
+            Some(due) if folded > saved => due.saturating_duration_since(Instant::now()),
+            _ => Duration::MAX,
+        };
+        match receiver.recv_timeout(wait) {
+            Ok((i, result)) => {
+                early.insert(i, result?);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        while let Some(entry) = early.first_entry().filter(|entry| *entry.key() == folded) {
+            let (partial, _, chunk_skipped) = entry.remove();
+            merge_partial(&mut stations, partial);
+            skipped.merge(&chunk_skipped);
+            folded += 1;
+        }
+        check_station_count(stations.len(), config)?;
+        if let (Some((path, interval)), Some(at)) = (checkpoint, due) {
+            if folded > saved && Instant::now() >= at {
+                save_checkpoint(path, resumed, &stations, bounds[folded], config)?;
+                saved = folded;
+                due = Some(Instant::now() + interval);
+            }
+        }
+    }
+    if let Some((path, _)) = checkpoint {
+        save_checkpoint(path, resumed, &stations, bounds[folded], config)?;
+    }
+    Ok((stations, skipped))
+}
+
+/// Save `resumed` merged with `stations` as covering the first `offset`
+/// bytes of the input.
+fn save_checkpoint(path: &Path, resumed: &StationMap<Vec<u8>>, stations: &StationMap<Vec<u8>>, offset: usize,
+                   config: &Config) -> Result<()> {
+    let mut snapshot = resumed.clone();
+    merge_stations(&mut snapshot, stations.clone());
+    state::write_checkpoint_file(path, &normalize_names(snapshot, config), offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aggregate, partition_slice};
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let names = ["Hamburg", "Bulawayo", "Palembang", "St. John's", "Cracow"];
+        let mut data: Vec<u8> = (0..500)
+            .flat_map(|i| format!("{};{}.{}\n", names[i % 5], (i % 97) as i32 - 40, i % 10).into_bytes())
+            .collect();
+        let expected = aggregate(&partition_slice(&data, 1), &Config::default()).unwrap().stations;
+        let path = std::env::temp_dir().join(format!("rs-1brc-{}-checkpoint", std::process::id()));
+        let config = Config {
+            threads: 1,
+            checkpoint_interval: Some(Duration::ZERO),
+            checkpoint_file: Some(path.clone()),
+            ..Config::default()
+        };
+        let empty = StationMap::with_hasher(StationHasher::from_config(&config));
+
+        // Crash partway through, on a malformed record, after checkpointing
+        // each chunk before it
+        let bad = data.len() / 2 + data[data.len() / 2..].iter().position(|&b| b == b';').unwrap();
+        let original = std::mem::replace(&mut data[bad], b'x');
+        assert!(aggregate_checkpointed_with(&data, 0, &empty, &config, &RunContext::default(), 256).is_err());
+        let (saved, covered) = state::read_checkpoint_file(path.to_str().unwrap(), &config).unwrap();
+        assert!(covered > 0 && covered <= bad, "covered {} of {}", covered, bad);
+        assert_eq!(data[covered - 1], b'\n');
+        assert!(saved.values().map(|s| s.count).sum::<u32>() > 0);
+
+        // After a fix, carrying on from the checkpoint counts every record once
+        data[bad] = original;
+        let config = Config { threads: 3, ..Config::default() };
+        let resumed = aggregate_checkpointed_with(&data, covered, &saved, &config, &RunContext::default(), 256).unwrap();
+        let before = data[..covered].iter().filter(|&&b| b == b'\n').count();
+        assert_eq!(before + resumed.readings(), 500);
+        let mut stations = saved;
+        merge_stations(&mut stations, resumed.stations);
+        assert_eq!(stations, expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_partition_flags_are_refused() {
+        let empty = StationMap::with_hasher(StationHasher::Fixed);
+        for (config, flag) in [
+            (Config { partitions: Some(4), ..Config::default() }, "--partitions"),
+            (Config { print_partitions: true, ..Config::default() }, "--print-partitions"),
+            (Config { partition_align: Align::Page, ..Config::default() }, "--partition-align"),
+            (Config { assert_sorted_input: true, ..Config::default() }, "--assert-sorted-input"),
+        ] {
+            let err = aggregate_file_checkpointed("measurements.txt", 0, &empty, &config, &RunContext::default()).unwrap_err();
+            assert!(err.to_string().starts_with(flag), "{}", err);
+        }
+    }
+}
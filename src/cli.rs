@@ -0,0 +1,661 @@
+use clap::{builder::RangedU64ValueParser, Args, Parser, Subcommand, ValueEnum};
+use std::{path::PathBuf, time::Duration};
+
+const MEASUREMENTS_TXT: &str = "data/measurements.txt";
+const NUM_THREADS: usize = 4;
+const MAX_STATIONS: usize = 10_000;
+const MAX_RECORD_LEN: usize = 1024 * 1024;
+
+/// 1 Billion Row Challenge in Rust
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+#[command(subcommand_precedence_over_arg = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub config: Config,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Generate a measurements file
+    Generate(GenerateArgs),
+    /// Aggregate several measurements files into one set of results
+    Merge {
+        /// Measurements files, or state files saved with --merge-output, to
+        /// combine
+        #[arg(required = true)]
+        inputs: Vec<String>,
+    },
+    /// Aggregate a measurements file and compare the output to expected results
+    Verify {
+        /// Measurements file to aggregate
+        input: String,
+        /// File holding the expected output
+        expected: PathBuf,
+    },
+    /// Compare two results files, reporting the stations whose stats differ
+    /// and those in only one of them
+    Compare {
+        /// Earlier results, in the verbose or official format
+        before: PathBuf,
+        /// Later results to compare with them
+        after: PathBuf,
+    },
+}
+
+/// Options controlling how measurements are aggregated and reported.
+#[derive(Debug, Clone, Parser)]
+pub struct Config {
+    /// Measurements file, or directory of files, to aggregate
+    #[arg(default_value = MEASUREMENTS_TXT)]
+    pub input: String,
+
+    /// Which files to aggregate when the input is a directory
+    #[arg(long, global = true, value_name = "PATTERN", default_value = "*")]
+    pub glob: glob::Pattern,
+
+    /// Number of worker threads
+    #[arg(short, long, global = true, default_value_t = NUM_THREADS,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub threads: usize,
+
+    /// Split the input into M partitions, shared out among the threads as
+    /// they finish, rather than one per thread
+    #[arg(long, global = true, value_name = "M",
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub partitions: Option<usize>,
+
+    /// Split the input into chunks of about MIB mebibytes, shared out among
+    /// the threads as they finish. 4 to 16 MiB keeps all threads busy on
+    /// uneven input without much per-chunk overhead
+    #[arg(long, global = true, value_name = "MIB", conflicts_with = "partitions",
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub chunk_size: Option<usize>,
+
+    /// Round partition split points up to this boundary, so each thread's
+    /// first read starts near the top of a cache line or page. Only affects
+    /// where the input is split, never the results
+    #[arg(long, global = true, value_enum, default_value_t = Align::None)]
+    pub partition_align: Align,
+
+    /// When the input is a directory, aggregate F of its files at once
+    #[arg(long, global = true, value_name = "F", default_value_t = 1,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub files_in_parallel: usize,
+
+    /// When the input is a directory, use T threads for each file rather
+    /// than an even share of --threads among the --files-in-parallel files
+    #[arg(long, global = true, value_name = "T",
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub threads_per_file: Option<usize>,
+
+    /// How the input file is read
+    #[arg(long, global = true, value_enum, default_value_t = Backend::Mmap)]
+    pub backend: Backend,
+
+    /// Read the file into memory rather than mapping it with the mmap
+    /// backend. A mapped file that another process truncates while it is
+    /// read kills this one with SIGBUS, while a copy is unaffected
+    #[arg(long, global = true)]
+    pub copy_on_open: bool,
+
+    /// Use the streaming backend for files larger than BYTES rather than
+    /// mapping them, with its read buffers shrunk to fit in BYTES. Station
+    /// maps and decompressed .zst input aren't counted
+    #[arg(long, global = true, value_name = "BYTES",
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub memory_limit: Option<usize>,
+
+    /// Number of reader threads for the streaming backend
+    #[arg(long, global = true, default_value_t = 1,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub io_threads: usize,
+
+    /// Write results to FILE instead of stdout
+    #[arg(short, long, global = true, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Append results to FILE, creating it if need be, rather than writing
+    /// them to stdout. Each run's results follow a `# TIMESTAMP INPUT rows=N`
+    /// header line, so the file keeps a history of runs
+    #[arg(long, global = true, value_name = "FILE", conflicts_with = "output")]
+    pub append_output: Option<PathBuf>,
+
+    /// Also save the merged results to FILE in a binary format that `merge`
+    /// accepts as an input
+    #[arg(long, global = true, value_name = "FILE")]
+    pub merge_output: Option<PathBuf>,
+
+    /// Under `merge`, report on stderr each station found in more than one
+    /// input, with its range and count in each and whether the ranges
+    /// overlap, as a sign of mixed datasets
+    #[arg(long, global = true, conflicts_with = "count_only")]
+    pub report_overlaps: bool,
+
+    /// Start from results saved with --merge-output, folding the new input
+    /// onto them, or from a --checkpoint-file, skipping the input it covers
+    #[arg(long, global = true, value_name = "FILE")]
+    pub resume: Option<PathBuf>,
+
+    /// Every SECONDS, save the results so far to --checkpoint-file. Given
+    /// to --resume, a checkpoint carries on from where it was taken, so a
+    /// crashed run can pick up without counting any record twice
+    #[arg(long, global = true, value_name = "SECONDS", value_parser = parse_seconds, requires = "checkpoint_file",
+          conflicts_with_all = ["count_only", "bench_parse_only", "metric", "sample_rate", "timeout"])]
+    pub checkpoint_interval: Option<Duration>,
+
+    /// File for --checkpoint-interval, replaced atomically with each new
+    /// checkpoint
+    #[arg(long, global = true, value_name = "FILE", requires = "checkpoint_interval")]
+    pub checkpoint_file: Option<PathBuf>,
+
+    /// Output format
+    #[arg(long, global = true, value_enum, default_value_t = Format::Verbose)]
+    pub format: Format,
+
+    /// Show the reading count in the text formats
+    #[arg(long, global = true, overrides_with = "no_count")]
+    pub include_count: bool,
+
+    /// Hide the reading count in the text formats
+    #[arg(long, global = true, overrides_with = "include_count")]
+    pub no_count: bool,
+
+    /// Group the digits of counts in threes with SEP, `,` if not given, in
+    /// the verbose and table formats and under --count-only. The official,
+    /// CSV and raw formats are never grouped
+    #[arg(long, global = true, value_name = "SEP", num_args = 0..=1, require_equals = true,
+          default_missing_value = ",")]
+    pub group_digits: Option<String>,
+
+    /// When to quote station names in the CSV format
+    #[arg(long, global = true, value_enum, default_value_t = CsvQuote::Minimal)]
+    pub csv_quote_policy: CsvQuote,
+
+    /// Add a column with a second per-station metric in the verbose, table
+    /// and CSV formats, computed in the same pass over the input as the
+    /// rest
+    #[arg(long, global = true, value_enum,
+          conflicts_with_all = ["count_only", "bench_parse_only", "merge_output", "resume", "sample_rate"])]
+    pub metric: Option<Metric>,
+
+    /// Decimal places for min/mean/max in the official, table and CSV
+    /// formats [default: as many as --decimals]
+    #[arg(long, global = true, value_name = "DIGITS")]
+    pub output_precision: Option<usize>,
+
+    /// How min/mean/max are rounded to the output precision
+    #[arg(long, global = true, value_enum, default_value_t = RoundMode::HalfUp)]
+    pub round_mode: RoundMode,
+
+    /// In the verbose and table formats, put an `== X ==` header before each
+    /// run of stations starting with the letter X
+    #[arg(long, global = true)]
+    pub group_by_initial: bool,
+
+    /// Truncate station names wider than this in the table format
+    #[arg(long, global = true, value_name = "CHARS", default_value_t = 32,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub name_width: usize,
+
+    /// Only print the stations named in FILE, one per line, in the file's
+    /// order. Listed stations with no readings are printed with `-` in place
+    /// of their stats
+    #[arg(long, global = true, value_name = "FILE")]
+    pub stations_file: Option<PathBuf>,
+
+    /// Fail if splitting the input finds no newline within BYTES of where a
+    /// partition should end, or the stream backend reads a record longer
+    /// than BYTES
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = MAX_RECORD_LEN,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_record_len: usize,
+
+    /// Only aggregate every K-th record of each partition, for a quick
+    /// approximate answer. Counts are scaled up by K, so counts and means are
+    /// estimates, and min and max are the extremes of the sampled records
+    /// only. Lines between samples aren't checked for errors
+    #[arg(long, global = true, value_name = "1/K", default_value = "1/1", value_parser = parse_sample_rate)]
+    pub sample_rate: usize,
+
+    /// Only aggregate the first BYTES of each input file, cut back to the end
+    /// of the last complete record within them, for a quick smoke test of a
+    /// large file
+    #[arg(long, global = true, value_name = "BYTES",
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub first_n_bytes: Option<usize>,
+
+    /// Check that the input is grouped by station, as in a sorted file,
+    /// failing if a station turns up again after a different one within a
+    /// partition. Runs of a station are looked up once either way, so this is
+    /// no faster than the default
+    #[arg(long, global = true)]
+    pub assert_sorted_input: bool,
+
+    /// Count each partition's lines in an extra pass first, and size its
+    /// station map for that many stations, up to --max-stations, so it never
+    /// grows while aggregating
+    #[arg(long, global = true)]
+    pub presize: bool,
+
+    /// Find each partition's distinct stations in an extra pass first, and
+    /// size its station map for exactly that many, so it neither grows nor
+    /// holds more room than it needs. The pass hashes every name, so this is
+    /// slower than --presize
+    #[arg(long, global = true, conflicts_with = "presize")]
+    pub two_pass: bool,
+
+    /// Records are `value;name` rather than `name;value`
+    #[arg(long, global = true)]
+    pub value_first: bool,
+
+    /// Split each line at its last `;` rather than its first, so station
+    /// names may contain `;`. By default a name with one is cut short and the
+    /// line is malformed
+    #[arg(long, global = true, conflicts_with = "value_first")]
+    pub last_delimiter: bool,
+
+    /// Fractional digits every reading has. Values are kept exactly in units
+    /// of the last digit, so at most 4 digits in all fit; state files hold
+    /// those units, so resume and merge them with the same setting
+    #[arg(long, global = true, value_name = "D", default_value_t = 1,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..=3))]
+    pub decimals: usize,
+
+    /// Ignore NUL bytes and spaces at the end of each line, as tools that pad
+    /// lines to a fixed width leave after the value. By default the padded
+    /// line is malformed
+    #[arg(long, global = true)]
+    pub trim: bool,
+
+    /// What to do with malformed lines
+    #[arg(long, global = true, value_enum, default_value_t = OnError::Fail)]
+    pub on_error: OnError,
+
+    /// Print how many malformed lines were skipped, and where, to stderr
+    #[arg(long, global = true)]
+    pub report_errors: bool,
+
+    /// Fail on station names that aren't valid UTF-8, reporting the byte
+    /// offset of the first. By default they are printed with replacement
+    /// characters, while stations are still told apart by their exact bytes
+    #[arg(long, global = true, visible_alias = "strict-utf8")]
+    pub strict: bool,
+
+    /// Collapse runs of spaces in station names to one space and drop any at
+    /// either end, so `New  York` and `New York ` count as `New York`. Off by
+    /// default, so names are kept byte for byte
+    #[arg(long, global = true)]
+    pub normalize_space: bool,
+
+    /// Abort once more than N distinct station names have been seen, which
+    /// usually means the input isn't in the expected format
+    #[arg(long, global = true, value_name = "N", default_value_t = MAX_STATIONS,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_stations: usize,
+
+    /// List stations with fewer than N readings to stderr, which are often
+    /// misspelled variants of another station
+    #[arg(long, global = true, value_name = "N")]
+    pub warn_rare: Option<u32>,
+
+    /// List stations whose max and min are more than DEGREES apart to
+    /// stderr, which often means distinct locations share a name or the data
+    /// is corrupt
+    #[arg(long, global = true, value_name = "DEGREES")]
+    pub max_span: Option<f64>,
+
+    /// Fail unless exactly N readings were processed
+    #[arg(long, global = true, value_name = "N")]
+    pub expect_rows: Option<usize>,
+
+    /// Skip checking that the merged station counts add up to the readings
+    /// processed
+    #[arg(long, global = true)]
+    pub no_merge_check: bool,
+
+    /// Only count each station's readings, printed as `name: count` lines
+    /// sorted by name or by descending count. Values aren't parsed or
+    /// checked, and no temperature stats are produced
+    #[arg(long, global = true, value_name = "ORDER", value_enum, num_args = 0..=1, require_equals = true,
+          default_missing_value = "name", conflicts_with_all = ["merge_output", "resume", "bench_parse_only"])]
+    pub count_only: Option<CountOrder>,
+
+    /// Values are plain integers, such as counts, rather than temperatures,
+    /// and each key's total is printed as `name: sum=S count=N` lines. Values
+    /// may have up to 18 digits. The totals can't be saved as state, which
+    /// holds temperature readings
+    #[arg(long, global = true,
+          conflicts_with_all = ["count_only", "decimals", "metric", "output_precision", "round_mode", "max_span",
+                                "bench_parse_only", "assert_sorted_input", "merge_output", "resume",
+                                "checkpoint_interval"])]
+    pub integer_values: bool,
+
+    /// Scan and parse every record but don't aggregate them, to measure
+    /// parsing apart from the map. No stations are reported
+    #[arg(long, global = true)]
+    pub bench_parse_only: bool,
+
+    /// Aggregate and merge as usual but don't write the results, to time
+    /// the compute alone. Combine with --stats for timings
+    #[arg(long, global = true, conflicts_with = "checksum")]
+    pub no_output: bool,
+
+    /// Print how the input was split into partitions to stderr
+    #[arg(long, global = true)]
+    pub print_partitions: bool,
+
+    /// Print the SHA-256 of the results output to stderr, for comparing runs
+    /// without diffing them
+    #[arg(long, global = true)]
+    pub checksum: bool,
+
+    /// Print how the input will be read, split and aggregated, and where the
+    /// results go, to stderr before processing it
+    #[arg(long, global = true)]
+    pub explain: bool,
+
+    /// Stop after SECONDS, which may be fractional. Workers stop at a record
+    /// boundary, what was read so far is merged and printed, and a note on
+    /// stderr says the counts are incomplete
+    #[arg(long, global = true, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub timeout: Option<Duration>,
+
+    /// Print per-thread diagnostics to stderr
+    #[arg(long, global = true)]
+    pub stats: bool,
+
+    /// How --stats is printed
+    #[arg(long, global = true, value_enum, default_value_t = TimeFormat::Text)]
+    pub time_format: TimeFormat,
+
+    /// Hash with fixed keys so internal iteration order is the same on every
+    /// run, which keeps diagnostics comparable between runs
+    #[arg(long, global = true)]
+    pub deterministic: bool,
+
+    /// Testing aid: merge the per-thread and per-file results in an order
+    /// shuffled with SEED, to catch output that depends on the merge order
+    #[arg(long, global = true, value_name = "SEED", hide = true)]
+    pub shuffle_merge_order: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::parse_from(["rs-1brc"])
+    }
+}
+
+impl Config {
+    /// Threads for each file of a directory input: --threads-per-file if
+    /// given, otherwise --threads shared among the files aggregated at once.
+    pub fn file_threads(&self) -> usize {
+        self.threads_per_file.unwrap_or((self.threads / self.files_in_parallel).max(1))
+    }
+
+    /// Digits after the point in each value, 0 for `--integer-values`.
+    pub fn value_decimals(&self) -> usize {
+        if self.integer_values {
+            0
+        } else {
+            self.decimals
+        }
+    }
+
+    /// Decimal places for min/mean/max, by default as many as the readings
+    /// have.
+    pub fn precision(&self) -> usize {
+        self.output_precision.unwrap_or(self.decimals)
+    }
+
+    /// Whether text output includes counts. The official format omits them
+    /// and the other formats show them, unless overridden.
+    pub fn show_count(&self) -> bool {
+        match (self.include_count, self.no_count) {
+            (true, _) => true,
+            (_, true) => false,
+            _ => self.format != Format::Official,
+        }
+    }
+}
+
+/// Parse a `--sample-rate` of the form `1/K` into K.
+fn parse_sample_rate(rate: &str) -> Result<usize, String> {
+    let every = rate.strip_prefix("1/").ok_or("expected a rate of the form 1/K")?;
+    match every.parse() {
+        Ok(0) | Err(_) => Err(format!("invalid sample interval `{}`, expected a positive integer", every)),
+        Ok(every) => Ok(every),
+    }
+}
+
+/// Parse a `--timeout` or `--checkpoint-interval` in seconds, which may be
+/// fractional.
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+    seconds
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("invalid duration `{}`, expected a number of seconds", seconds))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Map the whole file and give each thread one partition of it
+    Mmap,
+    /// Read the file in chunks on --io-threads readers, parsed by --threads
+    /// workers as they arrive
+    Stream,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Align {
+    /// Split at the first newline after an even share of the input
+    None,
+    /// Round split points up to a 64-byte cache line
+    CacheLine,
+    /// Round split points up to a memory page
+    Page,
+}
+
+impl Align {
+    /// The boundary in bytes that split points are rounded up to.
+    pub fn bytes(self) -> usize {
+        match self {
+            Align::None => 1,
+            Align::CacheLine => 64,
+            Align::Page => page_size(),
+        }
+    }
+}
+
+/// The system page size, assuming the common 4 KiB if it can't be read.
+fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// One `name: min= max= mean= count=` line per station
+    Verbose,
+    /// The challenge's `{name=min/mean/max, ...}` format
+    Official,
+    /// An aligned table with a header row
+    Table,
+    /// `station,min,mean,max,count` CSV with a header row
+    Csv,
+    /// `name min sum count max` lines of integers in tenths of a degree, or
+    /// units of the last digit under --decimals, for exact processing. The
+    /// mean is `sum / count`, and the name is written as is, so fields are
+    /// split from the right
+    Raw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CsvQuote {
+    /// Only names containing a comma, quote or line break
+    Minimal,
+    /// Every name
+    Always,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum RoundMode {
+    /// To the nearest, ties to even, as IEEE 754 does by default
+    Ieee,
+    /// To the nearest, ties up, as the challenge's reference implementation
+    /// does
+    #[default]
+    HalfUp,
+    /// Toward zero
+    Trunc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Metric {
+    /// Sum of the readings above zero, as `positive_sum`
+    PositiveSum,
+    /// Number of readings below zero, as `below_zero`
+    BelowZero,
+}
+
+impl Metric {
+    /// The metric's column name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::PositiveSum => "positive_sum",
+            Metric::BelowZero => "below_zero",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CountOrder {
+    /// Alphabetically by station name
+    Name,
+    /// Most readings first, ties by name
+    Count,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TimeFormat {
+    /// Per-thread diagnostics for reading
+    Text,
+    /// One JSON object per file with `elapsed_secs`, `rows`, `rows_per_sec`,
+    /// `bytes`, `bytes_per_sec` and `threads`, for benchmarking scripts
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Abort at the first malformed line
+    Fail,
+    /// Silently drop malformed lines
+    Skip,
+    /// Drop malformed lines, logging each to stderr
+    Warn,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct GenerateArgs {
+    /// Number of rows to generate
+    #[arg(short, long, default_value_t = 1_000_000)]
+    pub rows: u64,
+
+    /// Number of distinct stations
+    #[arg(short, long, default_value_t = 100,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub stations: usize,
+
+    /// Seed for the measurement values and shuffle order. The same seed and
+    /// options always produce the same file
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+
+    /// Write rows in random order instead of grouped by station. This holds
+    /// every row in memory (8 bytes each) until the shuffle is done
+    #[arg(long)]
+    pub shuffle: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_invocation() {
+        let cli = Cli::parse_from(["rs-1brc", "measurements.txt"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.config.input, "measurements.txt");
+        assert_eq!(cli.config.threads, NUM_THREADS);
+        assert_eq!(cli.config.format, Format::Verbose);
+    }
+
+    #[test]
+    fn test_subcommands_accept_global_flags() {
+        let cli = Cli::parse_from(["rs-1brc", "merge", "a.txt", "b.txt", "--threads", "2"]);
+        assert!(matches!(&cli.command, Some(Command::Merge { inputs }) if inputs == &["a.txt", "b.txt"]));
+        assert_eq!(cli.config.threads, 2);
+
+        assert!(Cli::try_parse_from(["rs-1brc", "--threads", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        assert_eq!(Config::default().sample_rate, 1);
+        assert_eq!(Config::parse_from(["rs-1brc", "--sample-rate", "1/8"]).sample_rate, 8);
+        for rate in ["2/3", "8", "1/0", "1/x"] {
+            assert!(Config::try_parse_from(["rs-1brc", "--sample-rate", rate]).is_err(), "{}", rate);
+        }
+    }
+
+    #[test]
+    fn test_file_threads() {
+        let config = |args: &[&str]| Config::parse_from(["rs-1brc"].iter().chain(args));
+        assert_eq!(config(&[]).file_threads(), NUM_THREADS);
+        assert_eq!(config(&["--threads", "8", "--files-in-parallel", "2"]).file_threads(), 4);
+        assert_eq!(config(&["--threads", "2", "--files-in-parallel", "3"]).file_threads(), 1);
+        assert_eq!(config(&["--files-in-parallel", "2", "--threads-per-file", "3"]).file_threads(), 3);
+    }
+
+    #[test]
+    fn test_count_only() {
+        let config = |args: &[&str]| Config::try_parse_from(["rs-1brc"].iter().chain(args));
+        assert_eq!(config(&[]).unwrap().count_only, None);
+        assert_eq!(config(&["--count-only", "in.txt"]).unwrap().count_only, Some(CountOrder::Name));
+        assert_eq!(config(&["--count-only=count"]).unwrap().count_only, Some(CountOrder::Count));
+        assert!(config(&["--count-only", "--resume", "state.bin"]).is_err());
+    }
+
+    #[test]
+    fn test_integer_values_are_not_saved_as_state() {
+        let config = |args: &[&str]| Config::try_parse_from(["rs-1brc", "--integer-values"].iter().chain(args));
+        assert!(config(&[]).is_ok());
+        for args in [&["--merge-output", "state.bin"][..], &["--resume", "state.bin"],
+                     &["--checkpoint-interval", "1", "--checkpoint-file", "state.bin"]] {
+            assert!(config(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_strict_utf8_alias() {
+        assert!(!Config::default().strict);
+        assert!(Config::parse_from(["rs-1brc", "--strict"]).strict);
+        assert!(Config::parse_from(["rs-1brc", "--strict-utf8"]).strict);
+    }
+
+    #[test]
+    fn test_show_count() {
+        let config = |args: &[&str]| Config::parse_from(["rs-1brc"].iter().chain(args));
+        assert!(config(&[]).show_count());
+        assert!(!config(&["--no-count"]).show_count());
+        assert!(!config(&["--format", "official"]).show_count());
+        assert!(config(&["--format", "official", "--include-count"]).show_count());
+        assert!(!config(&["--include-count", "--no-count"]).show_count());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::Config;
+use anyhow::{bail, Context, Result};
+use std::{collections::BTreeMap, io::Write, path::Path};
+
+/// One station's results as printed: the rounded stats, and the count if the
+/// output included it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Printed {
+    min: f64,
+    mean: f64,
+    max: f64,
+    count: Option<u64>,
+}
+
+/// Compare two results files station by station, writing each station whose
+/// min, mean, max or count differ, with the change, then those found in only
+/// one of them.
+///
+/// Both files must be in the verbose or official format, the latter with or
+/// without counts. Stats are printed rounded, so differences of up to one in
+/// the last of the `config.precision()` decimals are taken as rounding and
+/// not reported.
+pub fn compare(out: &mut impl Write, before: &Path, after: &Path, config: &Config) -> Result<()> {
+    let [old, new] = [before, after].map(|path| {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read file: {}", path.display()))
+            .and_then(|text| read_results(&text).with_context(|| format!("Failed to parse results: {}", path.display())))
+    });
+    let (old, new) = (old?, new?);
+    let precision = config.precision();
+    let tolerance = 10f64.powi(-(precision as i32)) * 1.000_001;
+
+    let mut differing = 0;
+    for (name, old_stats) in &old {
+        let Some(new_stats) = new.get(name) else {
+            continue;
+        };
+        let mut changes = Vec::new();
+        for (field, old_value, new_value) in [
+            ("min", old_stats.min, new_stats.min),
+            ("mean", old_stats.mean, new_stats.mean),
+            ("max", old_stats.max, new_stats.max),
+        ] {
+            if (new_value - old_value).abs() > tolerance {
+                changes.push(format!("{} {:.*} -> {:.*} ({:+.*})", field, precision, old_value, precision, new_value,
+                                     precision, new_value - old_value));
+            }
+        }
+        if let (Some(old_count), Some(new_count)) = (old_stats.count, new_stats.count) {
+            if old_count != new_count {
+                changes.push(format!("count {} -> {} ({:+})", old_count, new_count, new_count as i64 - old_count as i64));
+            }
+        }
+        if !changes.is_empty() {
+            writeln!(out, "{}: {}", name, changes.join(", "))?;
+            differing += 1;
+        }
+    }
+    let only = |results: &BTreeMap<String, Printed>, other: &BTreeMap<String, Printed>| -> Vec<String> {
+        results.keys().filter(|name| !other.contains_key(*name)).cloned().collect()
+    };
+    let (only_before, only_after) = (only(&old, &new), only(&new, &old));
+    for (path, names) in [(before, &only_before), (after, &only_after)] {
+        for name in names {
+            writeln!(out, "Only in {}: {}", path.display(), name)?;
+        }
+    }
+    if differing + only_before.len() + only_after.len() == 0 {
+        writeln!(out, "No differences, stations compared: {}", old.len())?;
+    } else {
+        writeln!(out, "Stations differing: {}, only in {}: {}, only in {}: {}", differing, before.display(),
+                 only_before.len(), after.display(), only_after.len())?;
+    }
+    Ok(())
+}
+
+/// Parse results in the verbose or official format, by station name.
+/// Listed stations without readings, printed as `-`, are left out.
+fn read_results(text: &str) -> Result<BTreeMap<String, Printed>> {
+    let mut results = BTreeMap::new();
+    let text = text.trim_end_matches('\n');
+    if let Some(entries) = text.strip_prefix('{') {
+        let entries = entries.strip_suffix('}').context("Missing closing `}`")?;
+        // Names may themselves hold ", ", so an entry only ends where what
+        // follows its last `=` parses as stats
+        let mut pending = String::new();
+        for piece in entries.split(", ").filter(|piece| !piece.is_empty()) {
+            if !pending.is_empty() {
+                pending.push_str(", ");
+            }
+            pending.push_str(piece);
+            let Some((name, stats)) = pending.rsplit_once('=') else {
+                continue;
+            };
+            if stats.split('/').take(3).all(|field| field == "-") {
+                pending.clear();
+                continue;
+            }
+            if let Some(printed) = official_stats(stats) {
+                results.insert(name.to_string(), printed);
+                pending.clear();
+            }
+        }
+        if !pending.is_empty() {
+            bail!("Unrecognized entry: {:?}", pending);
+        }
+        return Ok(results);
+    }
+
+    for (number, line) in text.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let (name, stats) = line
+            .rsplit_once(": min=")
+            .with_context(|| format!("Unrecognized line {}: {:?}", number + 1, line))?;
+        if stats.starts_with("- ") {
+            continue;
+        }
+        let printed = verbose_stats(stats).with_context(|| format!("Unrecognized line {}: {:?}", number + 1, line))?;
+        results.insert(name.to_string(), printed);
+    }
+    Ok(results)
+}
+
+/// Stats of the official format, `min/mean/max` with an optional `/count`.
+fn official_stats(stats: &str) -> Option<Printed> {
+    let fields: Vec<&str> = stats.split('/').collect();
+    let (numbers, count) = match fields[..] {
+        [min, mean, max] => ([min, mean, max], None),
+        [min, mean, max, count] => ([min, mean, max], Some(count.parse().ok()?)),
+        _ => return None,
+    };
+    let [min, mean, max] = numbers.map(str::parse);
+    Some(Printed { min: min.ok()?, mean: mean.ok()?, max: max.ok()?, count })
+}
+
+/// Stats of a verbose line after the `min=`: the min, then `key=value`
+/// fields of which `max`, `mean` and `count` are read and any others, such
+/// as a `--metric` column, ignored.
+fn verbose_stats(stats: &str) -> Option<Printed> {
+    let mut fields = stats.split(' ');
+    let min = fields.next()?.parse().ok()?;
+    let (mut max, mut mean, mut count) = (None, None, None);
+    for field in fields {
+        match field.split_once('=')? {
+            ("max", value) => max = Some(value.parse().ok()?),
+            ("mean", value) => mean = Some(value.parse().ok()?),
+            ("count", value) => count = Some(value.parse().ok()?),
+            _ => {}
+        }
+    }
+    Some(Printed { min, mean: mean?, max: max?, count })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_results() {
+        let verbose = read_results("Hamburg: min=12 max=34.2 mean=23.1 count=2\nSt. John's: min=-1.5 max=0 mean=-0.8\n\
+                                    Cracow: min=- max=- mean=- count=0\n").unwrap();
+        assert_eq!(verbose.len(), 2);
+        assert_eq!(verbose["Hamburg"], Printed { min: 12.0, mean: 23.1, max: 34.2, count: Some(2) });
+        assert_eq!(verbose["St. John's"], Printed { min: -1.5, mean: -0.8, max: 0.0, count: None });
+
+        let official = read_results("{Hamburg=12.0/23.1/34.2, Odd, Name=1.0/1.0/1.0, Cracow=-/-/-}\n").unwrap();
+        assert_eq!(official.len(), 2);
+        assert_eq!(official["Hamburg"], Printed { min: 12.0, mean: 23.1, max: 34.2, count: None });
+        assert_eq!(official["Odd, Name"].mean, 1.0);
+        let counted = read_results("{Hamburg=12.0/23.1/34.2/2, Cracow=-/-/-/0}").unwrap();
+        assert_eq!(counted.len(), 1);
+        assert_eq!(counted["Hamburg"].count, Some(2));
+
+        assert!(read_results("Hamburg 12.0\n").is_err());
+        assert!(read_results("{Hamburg=12.0/twenty/34.2}").is_err());
+    }
+}
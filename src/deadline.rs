@@ -0,0 +1,44 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// When `--timeout` stops the run, shared by every clone of it.
+///
+/// Workers poll [`expired`](Deadline::expired) between slices of their
+/// partition rather than per record. The first to see the time is up raises
+/// a shared flag, so the rest stop at their next check without reading the
+/// clock, and [`fired`](Deadline::fired) tells afterwards whether any work was
+/// cut short. A run that finishes before anyone checks late isn't affected.
+#[derive(Debug, Clone)]
+pub struct Deadline {
+    at: Instant,
+    stop: Arc<AtomicBool>,
+}
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Deadline { at: Instant::now() + timeout, stop: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Whether to stop now, raising the flag for everyone else if the time is
+    /// up.
+    pub fn expired(&self) -> bool {
+        if self.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        let expired = Instant::now() >= self.at;
+        if expired {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+        expired
+    }
+
+    /// Whether some work was skipped because the deadline passed.
+    pub fn fired(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+}
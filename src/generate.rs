@@ -0,0 +1,93 @@
+use crate::cli::GenerateArgs;
+use std::io::{self, Write};
+
+/// SplitMix64, which is plenty for test data and keeps output reproducible
+/// for a given seed.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// Shuffle `items` in place (Fisher-Yates).
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i as u64 + 1) as usize);
+        }
+    }
+}
+
+/// Write `args.rows` measurements to `out`.
+///
+/// Rows are grouped by station, each station's readings scattered around its
+/// own mean and kept within the challenge's -99.9..=99.9 range. With
+/// `args.shuffle` the same rows are written in a seeded random order, so keys
+/// arrive interleaved rather than in long runs.
+pub fn generate(args: &GenerateArgs, out: &mut impl Write) -> io::Result<()> {
+    let mut rng = Rng::new(args.seed);
+    let means: Vec<i16> = (0..args.stations).map(|_| rng.below(701) as i16 - 300).collect();
+    let mut rows = (0..args.rows).map(|row| {
+        let station = (row as u128 * args.stations as u128 / args.rows as u128) as usize;
+        let value = (means[station] + rng.below(301) as i16 - 150).clamp(-999, 999);
+        (station as u32, value)
+    });
+    if !args.shuffle {
+        return rows.try_for_each(|(station, value)| write_row(out, station, value));
+    }
+    let mut rows: Vec<_> = rows.collect();
+    rng.shuffle(&mut rows);
+    rows.into_iter().try_for_each(|(station, value)| write_row(out, station, value))
+}
+
+fn write_row(out: &mut impl Write, station: u32, value: i16) -> io::Result<()> {
+    writeln!(out, "Station{};{:.1}", station, value as f64 / 10.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_is_reproducible() {
+        let args = GenerateArgs { rows: 1000, stations: 10, seed: 7, shuffle: false };
+        let (mut first, mut second) = (Vec::new(), Vec::new());
+        generate(&args, &mut first).unwrap();
+        generate(&args, &mut second).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.iter().filter(|&&b| b == b'\n').count(), 1000);
+    }
+
+    #[test]
+    fn test_shuffle_reorders_the_same_rows() {
+        let args = GenerateArgs { rows: 1000, stations: 10, seed: 7, shuffle: true };
+        let (mut first, mut second) = (Vec::new(), Vec::new());
+        generate(&args, &mut first).unwrap();
+        generate(&args, &mut second).unwrap();
+        assert_eq!(first, second);
+
+        let mut grouped = Vec::new();
+        generate(&GenerateArgs { shuffle: false, ..args }, &mut grouped).unwrap();
+        assert_ne!(first, grouped);
+        let sorted = |data: &[u8]| {
+            let mut lines: Vec<_> = data.split(|&b| b == b'\n').map(<[u8]>::to_vec).collect();
+            lines.sort();
+            lines
+        };
+        assert_eq!(sorted(&first), sorted(&grouped));
+    }
+}
@@ -0,0 +1,61 @@
+use crate::Config;
+use anyhow::Result;
+
+/// Whether an input names an HTTP(S) URL rather than a local path.
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Download the body of `url` into memory.
+///
+/// A `Content-Encoding` such as gzip is decoded transparently. When the
+/// server sends a `Content-Length` the buffer is sized for it up front, and
+/// `--stats` reports the bytes received against it.
+#[cfg(feature = "http")]
+pub fn fetch(url: &str, config: &Config) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    let response = ureq::get(url).call()?;
+    let expected = response.body().content_length();
+    let mut body = Vec::with_capacity(expected.unwrap_or(0) as usize);
+    response.into_body().into_reader().read_to_end(&mut body)?;
+    if config.stats {
+        match expected {
+            Some(expected) => eprintln!("Downloaded {} bytes of {} from {}", body.len(), expected, url),
+            None => eprintln!("Downloaded {} bytes from {}", body.len(), url),
+        }
+    }
+    Ok(body)
+}
+
+#[cfg(not(feature = "http"))]
+pub fn fetch(_url: &str, _config: &Config) -> Result<Vec<u8>> {
+    anyhow::bail!("Reading from a URL requires building with the `http` feature")
+}
+
+#[cfg(all(test, feature = "http"))]
+mod tests {
+    use super::*;
+    use crate::aggregate_file;
+    use std::{io::Write, net::TcpListener};
+
+    #[test]
+    fn test_fetch_from_local_server() {
+        let body = b"Hamburg;12.0\nBulawayo;8.9\nHamburg;34.2\n";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/measurements.txt", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // The request fits in one read, and its contents don't matter
+            let mut request = [0; 4096];
+            std::io::Read::read(&mut stream, &mut request).unwrap();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+
+        let aggregation = aggregate_file(&url, &Config::default()).unwrap();
+        server.join().unwrap();
+        assert_eq!(aggregation.readings(), 3);
+        assert_eq!(aggregation.stations[&b"Hamburg"[..]].count(), 2);
+    }
+}
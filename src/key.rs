@@ -0,0 +1,115 @@
+use std::{
+    borrow::Borrow,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+/// A station name stored inline, to key a map with owned names without a
+/// heap allocation per station.
+///
+/// Names of up to [`CAPACITY`](StationKey::CAPACITY) bytes fit, a little over
+/// the 100 bytes the challenge allows. Equality and hashing only look at the
+/// used bytes, and hash as the name's `[u8]` does, so a map keyed by
+/// `StationKey` can be looked up with a `&[u8]`.
+#[derive(Clone, Copy)]
+pub struct StationKey {
+    len: u8,
+    bytes: [u8; StationKey::CAPACITY],
+}
+
+impl StationKey {
+    pub const CAPACITY: usize = 104;
+
+    /// A key holding `name`, or `None` if it is longer than
+    /// [`CAPACITY`](StationKey::CAPACITY).
+    #[inline(always)]
+    pub fn new(name: &[u8]) -> Option<Self> {
+        if name.len() > Self::CAPACITY {
+            return None;
+        }
+        let mut bytes = [0; Self::CAPACITY];
+        bytes[..name.len()].copy_from_slice(name);
+        Some(StationKey { len: name.len() as u8, bytes })
+    }
+
+    #[inline(always)]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl PartialEq for StationKey {
+    #[inline(always)]
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for StationKey {}
+
+impl Hash for StationKey {
+    #[inline(always)]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
+    }
+}
+
+impl Borrow<[u8]> for StationKey {
+    fn borrow(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for StationKey {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl fmt::Debug for StationKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StationKey({:?})", String::from_utf8_lossy(self.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{StationHasher, StationMap};
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn test_prefixes_are_distinct_keys() {
+        let hasher = StationHasher::Fixed;
+        let [short, long] = [&b"Abc"[..], b"Abcd"].map(|name| StationKey::new(name).unwrap());
+        assert_ne!(short, long);
+        assert_ne!(hasher.hash_one(short), hasher.hash_one(long));
+        assert_eq!(short, StationKey::new(b"Abc").unwrap());
+        assert_eq!(hasher.hash_one(short), hasher.hash_one(&b"Abc"[..]));
+
+        // Bytes past the end of a name don't count, even when they differ
+        let mut stale = StationKey::new(b"Abcd").unwrap();
+        stale.len = 3;
+        assert_eq!(stale, short);
+        assert_eq!(hasher.hash_one(stale), hasher.hash_one(short));
+    }
+
+    #[test]
+    fn test_lookup_by_slice() {
+        let mut stations = StationMap::with_hasher(StationHasher::Fixed);
+        for name in [&b"Oslo"[..], b"Os", b"Oslo", b""] {
+            stations.entry(StationKey::new(name).unwrap()).or_default().add_measurement(1);
+        }
+        assert_eq!(stations.len(), 3);
+        assert_eq!(stations[&b"Oslo"[..]].count(), 2);
+        assert_eq!(stations[&b"Os"[..]].count(), 1);
+        assert!(!stations.contains_key(&b"Osl"[..]));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let name = [b'x'; StationKey::CAPACITY];
+        assert_eq!(StationKey::new(&name).unwrap().as_bytes(), name);
+        assert!(StationKey::new(&[b'x'; StationKey::CAPACITY + 1]).is_none());
+    }
+}
@@ -0,0 +1,3496 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use libc::c_void;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{
+        hash_map::{DefaultHasher, RandomState},
+        BTreeMap, HashSet,
+    },
+    fmt,
+    fs::{File, OpenOptions},
+    hash::BuildHasher,
+    io::{self, BufWriter, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+mod checkpoint;
+mod cli;
+mod compare;
+mod deadline;
+mod generate;
+mod http;
+mod key;
+mod output;
+mod records;
+mod reducer;
+mod state;
+mod stream;
+
+pub use cli::{Align, Backend, Cli, Command, Config, CountOrder, CsvQuote, Format, GenerateArgs, Metric, OnError, RoundMode, TimeFormat};
+pub use deadline::Deadline;
+pub use generate::{generate, Rng};
+pub use key::StationKey;
+pub use output::{write_listed, write_output, write_results, MetricColumn};
+pub use records::{Malformed, RecordIter};
+pub use reducer::{BelowZero, PositiveSum, Reducer, ReducerMap};
+pub use state::{read_state, write_state};
+
+use records::{next_record_start, prev_record_start, trim_padding};
+
+/// A reading in tenths of a degree, or in units of its last digit under
+/// `--decimals`. `i16` holds every valid reading; the
+/// `wide-measurements` feature makes it `i32` instead, to measure whether the
+/// narrower type is worth it.
+#[cfg(not(feature = "wide-measurements"))]
+pub type Measurement = i16;
+#[cfg(feature = "wide-measurements")]
+pub type Measurement = i32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeatherStation {
+    min: Measurement,
+    max: Measurement,
+    sum: i64,
+    count: u32,
+}
+
+impl WeatherStation {
+    /// A station with no readings. Its count is 0 and `min`/`max` hold
+    /// sentinels that any reading replaces, so it only means something once
+    /// a reading is added or another station is merged in.
+    pub fn new() -> Self {
+        WeatherStation {
+            min: Measurement::MAX,
+            max: Measurement::MIN,
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    /// A station holding the one reading `measurement`.
+    #[inline(always)]
+    pub fn from_single(measurement: Measurement) -> Self {
+        WeatherStation {
+            min: measurement,
+            max: measurement,
+            sum: measurement as i64,
+            count: 1,
+        }
+    }
+
+    #[inline(always)]
+    pub fn add_measurement(&mut self, measurement: Measurement) {
+        self.min = self.min.min(measurement);
+        self.max = self.max.max(measurement);
+        self.sum += measurement as i64;
+        self.count += 1;
+    }
+
+    pub fn merge(&mut self, other: &WeatherStation) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    /// Stand in for `factor` times as many readings with the same mean, to
+    /// extrapolate from a sample.
+    pub fn scale(&mut self, factor: u32) {
+        self.sum *= factor as i64;
+        self.count = self.count.saturating_mul(factor);
+    }
+
+    pub fn min(&self) -> f32 {
+        self.min as f32 / 10.0
+    }
+
+    pub fn max(&self) -> f32 {
+        self.max as f32 / 10.0
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.count as f64 / 10.0
+    }
+
+    /// Min, max and mean in degrees for readings with `decimals` fractional
+    /// digits, which [`min`](Self::min) and the others take to be one.
+    pub fn degrees(&self, decimals: usize) -> (f32, f32, f64) {
+        let unit = 10f64.powi(decimals as i32);
+        (self.min as f32 / unit as f32, self.max as f32 / unit as f32, self.sum as f64 / self.count as f64 / unit)
+    }
+
+    /// Lowest reading, in tenths of a degree.
+    pub fn min_raw(&self) -> Measurement {
+        self.min
+    }
+
+    /// Highest reading, in tenths of a degree.
+    pub fn max_raw(&self) -> Measurement {
+        self.max
+    }
+
+    /// Sum of all readings, in tenths of a degree.
+    pub fn sum_raw(&self) -> i64 {
+        self.sum
+    }
+
+    /// Number of readings.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn rounded_min(&self) -> Rounded {
+        Rounded::new(self.min as i64, 1)
+    }
+
+    pub fn rounded_max(&self) -> Rounded {
+        Rounded::new(self.max as i64, 1)
+    }
+
+    pub fn rounded_mean(&self) -> Rounded {
+        Rounded::new(self.sum, self.count as i64)
+    }
+
+    /// Min, mean and max rounded as `mode` asks, for readings with
+    /// `decimals` fractional digits.
+    pub fn rounded(&self, mode: RoundMode, decimals: usize) -> RoundedStats {
+        let [min, mean, max] = [self.rounded_min(), self.rounded_mean(), self.rounded_max()]
+            .map(|stat| stat.with_mode(mode).with_decimals(decimals));
+        RoundedStats { min, mean, max }
+    }
+}
+
+impl Default for WeatherStation {
+    fn default() -> Self {
+        WeatherStation::new()
+    }
+}
+
+/// Formats as the canonical `min/mean/max`, rounded half up from tenths to
+/// one decimal place unless the formatter asks for another precision.
+impl fmt::Display for WeatherStation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.rounded(RoundMode::HalfUp, 1), f)
+    }
+}
+
+/// A station's [`Rounded`] min, mean and max, which display as
+/// `min/mean/max` at the formatter's precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundedStats {
+    pub min: Rounded,
+    pub mean: Rounded,
+    pub max: Rounded,
+}
+
+impl fmt::Display for RoundedStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precision = f.precision().unwrap_or(1);
+        write!(f, "{:.*}/{:.*}/{:.*}", precision, self.min, precision, self.mean, precision, self.max)
+    }
+}
+
+/// A value of `units / count` tenths of a degree, or of the unit
+/// [`with_decimals`](Rounded::with_decimals) sets.
+///
+/// Displays rounded to the formatter's precision (one decimal place by
+/// default), half up as the challenge's reference implementation does unless
+/// [`with_mode`](Rounded::with_mode) picks another [`RoundMode`]. The rounding
+/// is done on integers, so a mean of exactly 1.25 always prints as 1.3 half
+/// up, or 1.2 ties to even, rather than depending on how 1.25 happens to be
+/// represented as a float.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rounded {
+    units: i64,
+    count: i64,
+    mode: RoundMode,
+    decimals: u32,
+}
+
+impl Rounded {
+    pub fn new(tenths: i64, count: i64) -> Self {
+        Rounded { units: tenths, count, mode: RoundMode::HalfUp, decimals: 1 }
+    }
+
+    pub fn with_mode(self, mode: RoundMode) -> Self {
+        Rounded { mode, ..self }
+    }
+
+    /// Count in units of `10^-decimals` degrees rather than tenths.
+    pub fn with_decimals(self, decimals: usize) -> Self {
+        Rounded { decimals: decimals as u32, ..self }
+    }
+}
+
+impl fmt::Display for Rounded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "NaN");
+        }
+        let precision = f.precision().unwrap_or(1);
+        // Scale to units of the last printed digit, then round the quotient
+        let scale = 10i128.pow(precision as u32);
+        let num = self.units as i128 * scale;
+        let den = self.count as i128 * 10i128.pow(self.decimals);
+        let (floor, rem) = (num.div_euclid(den), num.rem_euclid(den));
+        let round_up = match self.mode {
+            RoundMode::Ieee => 2 * rem > den || (2 * rem == den && floor % 2 != 0),
+            RoundMode::HalfUp => 2 * rem >= den,
+            RoundMode::Trunc => num < 0 && rem != 0,
+        };
+        let scaled = floor + round_up as i128;
+        let sign = if scaled < 0 { "-" } else { "" };
+        let (int, frac) = (scaled.abs() / scale, scaled.abs() % scale);
+        if precision == 0 {
+            write!(f, "{}{}", sign, int)
+        } else {
+            write!(f, "{}{}.{:0width$}", sign, int, frac, width = precision)
+        }
+    }
+}
+
+/// Hasher for station maps.
+///
+/// `Fixed` uses the same SipHash as the default but with fixed keys, so maps
+/// built from the same input iterate in the same order on every run. Speed
+/// should match the default, though a different layout can make any one run a
+/// little slower or faster.
+#[derive(Debug, Clone)]
+pub enum StationHasher {
+    Random(RandomState),
+    Fixed,
+}
+
+impl Default for StationHasher {
+    fn default() -> Self {
+        StationHasher::Random(RandomState::new())
+    }
+}
+
+impl StationHasher {
+    pub fn from_config(config: &Config) -> Self {
+        if config.deterministic {
+            StationHasher::Fixed
+        } else {
+            StationHasher::default()
+        }
+    }
+}
+
+impl BuildHasher for StationHasher {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        match self {
+            StationHasher::Random(state) => state.build_hasher(),
+            StationHasher::Fixed => DefaultHasher::new(),
+        }
+    }
+}
+
+pub type StationMap<K> = ReducerMap<K, WeatherStation>;
+
+/// A read-only, private mapping of a whole file.
+///
+/// The mapping reads the file's pages as they are touched, so if another
+/// process truncates the file while it is mapped, touching the pages past the
+/// new end raises SIGBUS and kills the process. `--copy-on-open` reads the
+/// file into memory instead, for files another process may still be
+/// writing.
+#[derive(Debug)]
+pub struct MmappedFile {
+    _file: File,
+    data: *const c_void,
+    len: usize,
+}
+
+#[allow(dead_code)]
+impl MmappedFile {
+    pub fn new(file: File) -> Result<Self> {
+        let metadata = file.metadata()?;
+        if metadata.is_dir() {
+            // Opening a directory succeeds, but its length is meaningless and
+            // mapping it fails with an unhelpful error
+            bail!("Expected a file, got a directory");
+        }
+        let len = mapping_len(metadata.len())?;
+        if len == 0 {
+            // mmap rejects zero-length mappings, and there is nothing to read
+            let data = std::ptr::NonNull::<u8>::dangling().as_ptr() as *const c_void;
+            return Ok(MmappedFile { _file: file, data, len });
+        }
+        let data = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if data == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(MmappedFile { _file: file, data, len })
+    }
+
+    /// The whole mapping as a byte slice.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: `data` is a read-only mapping of `len` bytes (or dangling
+        // when `len` is 0) that stays mapped until `self` is dropped
+        unsafe { std::slice::from_raw_parts(self.data as *const u8, self.len) }
+    }
+
+    /// Split the mapping as [`try_partition_slice`] does.
+    pub fn partition_into_slices(&self, num_partitions: usize, max_record_len: usize) -> Result<Vec<&[u8]>> {
+        try_partition_slice(self.as_slice(), num_partitions, max_record_len)
+    }
+}
+
+/// A local file's contents, mapped, or read into memory under
+/// `--copy-on-open`.
+enum Contents {
+    Mapped(MmappedFile),
+    Copied(Vec<u8>),
+}
+
+impl Contents {
+    fn load(mut file: File, config: &Config) -> Result<Self> {
+        if !config.copy_on_open {
+            return Ok(Contents::Mapped(MmappedFile::new(file).context("Failed to mmap file")?));
+        }
+        let mut data = Vec::with_capacity(mapping_len(file.metadata()?.len())?);
+        io::Read::read_to_end(&mut file, &mut data).context("Failed to read file")?;
+        Ok(Contents::Copied(data))
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Contents::Mapped(mmap) => mmap.as_slice(),
+            Contents::Copied(data) => data,
+        }
+    }
+}
+
+/// Split `data` into `num_partitions` slices that each end just after a
+/// newline, so no record straddles two partitions.
+pub fn partition_slice(data: &[u8], num_partitions: usize) -> Vec<&[u8]> {
+    try_partition_slice(data, num_partitions, usize::MAX).expect("unbounded newline search can't fail")
+}
+
+/// Like [`partition_slice`], but fail if there is no newline within
+/// `max_record_len` bytes of where a partition should end. Without the cap,
+/// a file with a huge line or no newlines at all quietly ends up in one
+/// partition.
+pub fn try_partition_slice(data: &[u8], num_partitions: usize, max_record_len: usize) -> Result<Vec<&[u8]>> {
+    try_partition_slice_aligned(data, num_partitions, max_record_len, 1)
+}
+
+/// Like [`try_partition_slice`], but round each split point up to an
+/// `align`-byte boundary in memory before looking for the newline, so each
+/// partition starts just past a boundary rather than anywhere in a page. The
+/// record straddling the boundary stays with the earlier partition.
+pub fn try_partition_slice_aligned(data: &[u8], num_partitions: usize, max_record_len: usize, align: usize)
+    -> Result<Vec<&[u8]>>
+{
+    if num_partitions == 0 {
+        bail!("Can't split the input into 0 partitions");
+    }
+    let partition_size = data.len() / num_partitions;
+    let base = data.as_ptr() as usize;
+    let mut partitions = Vec::with_capacity(num_partitions);
+    let mut start: usize = 0;
+    for _ in 0..num_partitions {
+        // Find suitable end point
+        let end = ((base + start + partition_size).next_multiple_of(align) - base).min(data.len());
+
+        // Move it on to the next record start, keeping the record holding byte
+        // `end` in this partition
+        let end = match next_record_start(data, end + 1) {
+            next if next - end > max_record_len => {
+                bail!("Record too long: no newline within {} bytes of byte {}", max_record_len, end)
+            }
+            next => next,
+        };
+
+        debug_assert!(start == 0 || start == data.len() || data[start - 1] == b'\n',
+                      "partition starts mid-record at byte {}", start);
+        partitions.push(&data[start..end]);
+        start = end;
+    }
+    Ok(partitions)
+}
+
+/// Convert a file length to a mapping length, refusing files too large to map
+/// in full rather than silently truncating them on 32-bit targets.
+fn mapping_len(len: u64) -> Result<usize> {
+    usize::try_from(len).map_err(|_| {
+        anyhow!("File is too large to map on this platform: {} bytes exceeds {} bytes", len, usize::MAX)
+    })
+}
+
+impl Drop for MmappedFile {
+    fn drop(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+        unsafe {
+            libc::munmap(self.data as *mut c_void, self.len);
+        }
+    }
+}
+
+/// Parse a reading without validating it. Malformed input, including an
+/// empty or sign-only value, gives a meaningless result rather than a panic;
+/// use [`try_parse_measurement`] to reject it.
+///
+/// Values of up to 8 bytes after the sign, which covers every valid one, are
+/// parsed as a single word rather than a byte at a time.
+#[inline(always)]
+pub fn parse_measurement(measurement: &[u8]) -> Measurement {
+    let neg = measurement.first() == Some(&b'-');
+    let digits = &measurement[neg as usize..];
+    if digits.len() > 8 {
+        return parse_measurement_scalar(measurement);
+    }
+
+    let word = right_aligned_word(digits);
+
+    // Keep the low nibble of each digit byte, which is its value, and drop
+    // the point, then close the gap it leaves
+    let word = word & 0x0000_000F_0F0F_000F;
+    let word = (word & 0xFF) | ((word >> 8) & !0xFF);
+
+    // Combine neighbouring digits into two-digit lanes, then the two lanes
+    // into the value in tenths. No lane can carry into the next
+    let pairs = (word.wrapping_mul(10 + (1 << 8)) >> 8) & 0x00FF_00FF;
+    let value = ((pairs.wrapping_mul(100 + (1 << 16)) >> 16) & 0xFFFF) as Measurement;
+    if neg {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Load up to 8 bytes into a zero-padded big-endian word ending at its low
+/// byte, so the tenths digit is always the low byte and the point the one
+/// above it, whatever the length. Two fixed-size loads from either end
+/// overlap in the middle, where they agree, which avoids a variable-length
+/// copy.
+#[inline(always)]
+fn right_aligned_word(bytes: &[u8]) -> u64 {
+    let len = bytes.len();
+    debug_assert!(len <= 8);
+    if len >= 4 {
+        let head = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as u64;
+        let tail = u32::from_be_bytes(bytes[len - 4..].try_into().unwrap()) as u64;
+        (head << (8 * (len - 4))) | tail
+    } else if len >= 2 {
+        let head = u16::from_be_bytes(bytes[..2].try_into().unwrap()) as u64;
+        let tail = u16::from_be_bytes(bytes[len - 2..].try_into().unwrap()) as u64;
+        (head << (8 * (len - 2))) | tail
+    } else {
+        bytes.first().map_or(0, |&b| b as u64)
+    }
+}
+
+/// Byte-at-a-time parsing, for values too long for [`parse_measurement`]'s
+/// single word.
+fn parse_measurement_scalar(measurement: &[u8]) -> Measurement {
+    let neg: bool = measurement.first() == Some(&b'-');
+    let mut value: Measurement = 0;
+    let mut i: usize = if neg { 1 } else { 0 };
+    while i < measurement.len() {
+        if measurement[i] == b'.' {
+            i += 1;
+            continue;
+        }
+        // Wrap rather than overflow, so garbage gives garbage, not a panic
+        value = value.wrapping_mul(10).wrapping_add(measurement[i].wrapping_sub(b'0') as Measurement);
+        i += 1;
+    }
+    if neg {
+        value = value.wrapping_neg();
+    }
+    value
+}
+
+/// Most integer digits a reading may have. The challenge only needs two, but
+/// anything up to 999.9 fits in an `i16` without overflowing.
+const MAX_INTEGER_DIGITS: usize = 3;
+
+/// Parse a reading, returning `None` unless it has the `-?D+.D` shape with at
+/// most [`MAX_INTEGER_DIGITS`] before the point.
+#[inline(always)]
+pub fn try_parse_measurement(measurement: &[u8]) -> Option<Measurement> {
+    let digits = measurement.strip_prefix(b"-").unwrap_or(measurement);
+    match digits {
+        [int @ .., b'.', frac]
+            if (1..=MAX_INTEGER_DIGITS).contains(&int.len())
+                && int.iter().all(u8::is_ascii_digit)
+                && frac.is_ascii_digit() =>
+        {
+            Some(parse_measurement(measurement))
+        }
+        _ => None,
+    }
+}
+
+/// Parse a reading with `decimals` digits after the point, for
+/// `--decimals`, in units of its last digit. Returns `None` unless it has
+/// that shape and at most [`MAX_INTEGER_DIGITS`]` + 1` digits in all, so that
+/// it fits wherever a reading in tenths does.
+pub fn try_parse_decimals(measurement: &[u8], decimals: usize) -> Option<Measurement> {
+    let digits = measurement.strip_prefix(b"-").unwrap_or(measurement);
+    let point = digits.len().checked_sub(decimals + 1)?;
+    let (int, frac) = (&digits[..point], &digits[point + 1..]);
+    let valid = digits[point] == b'.'
+        && (1..=(MAX_INTEGER_DIGITS + 1).saturating_sub(decimals)).contains(&int.len())
+        && int.iter().chain(frac).all(u8::is_ascii_digit);
+    valid.then(|| parse_measurement_scalar(measurement))
+}
+
+/// Parse a plain integer value, for `--integer-values`, returning `None`
+/// unless it is `-?D+` with at most 18 digits, which always fit in an `i64`.
+pub fn try_parse_integer(value: &[u8]) -> Option<i64> {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    if !(1..=18).contains(&digits.len()) || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let magnitude = digits.iter().fold(0i64, |acc, &digit| acc * 10 + (digit - b'0') as i64);
+    Some(if digits.len() < value.len() { -magnitude } else { magnitude })
+}
+
+pub fn thread_runner<'a>(data: &'a [u8], config: &Config) -> Result<(StationMap<&'a [u8]>, usize)> {
+    run_partition(data, 0, config, &RunContext::default()).map(|(stations, readings, _)| (stations, readings))
+}
+
+/// Bytes scanned between checks of the `--timeout` deadline: a few
+/// milliseconds of work, so the run stops soon after it passes.
+const DEADLINE_SLICE: usize = 1024 * 1024;
+
+/// What every partition of one run shares besides the config, set up as
+/// [`run`] starts.
+#[derive(Debug, Default)]
+struct RunContext {
+    /// When `--timeout` stops the run.
+    deadline: Option<Deadline>,
+    /// The `--metric` values, which each partition adds to as it is scanned.
+    metric: Option<Mutex<MetricTally>>,
+}
+
+/// Each station's `--metric` reducer, tallied beside its stats in the same
+/// scan of the input. Only the map for the run's metric is filled.
+#[derive(Debug, Default)]
+struct MetricTally {
+    positive_sum: ReducerMap<Vec<u8>, PositiveSum>,
+    below_zero: ReducerMap<Vec<u8>, BelowZero>,
+}
+
+/// Aggregate one partition that starts `offset` bytes into the file.
+fn run_partition<'a>(data: &'a [u8], offset: usize, config: &Config, context: &RunContext)
+                     -> Result<(StationMap<&'a [u8]>, usize, Skipped)> {
+    match &context.deadline {
+        Some(deadline) => run_until(data, offset, config, context, deadline),
+        None => scan_partition(data, offset, config, context),
+    }
+}
+
+/// Aggregate a partition a slice at a time, stopping at the first slice
+/// boundary after the deadline, so the record loops stay free of checks.
+fn run_until<'a>(data: &'a [u8], offset: usize, config: &Config, context: &RunContext, deadline: &Deadline)
+                 -> Result<(StationMap<&'a [u8]>, usize, Skipped)> {
+    let mut stations = StationMap::with_hasher(StationHasher::from_config(config));
+    let (mut readings, mut skipped) = (0, Skipped::default());
+    let mut start = 0;
+    while start < data.len() && !deadline.expired() {
+        let end = next_record_start(data, start + DEADLINE_SLICE);
+        let (partial, slice_readings, slice_skipped) = scan_partition(&data[start..end], offset + start, config, context)?;
+        for (name, station) in partial {
+            stations.entry(name).or_default().merge(&station);
+        }
+        check_station_count(stations.len(), config)?;
+        readings += slice_readings;
+        skipped.merge(&slice_skipped);
+        start = end;
+    }
+    Ok((stations, readings, skipped))
+}
+
+/// Aggregate a partition, or a slice of one, in a single pass.
+fn scan_partition<'a>(data: &'a [u8], offset: usize, config: &Config, context: &RunContext)
+                      -> Result<(StationMap<&'a [u8]>, usize, Skipped)> {
+    // Pick the record loop once per partition so the per-row code has no
+    // config branches left in it. Value-first names run to the end of the
+    // line, so they already keep any `;` and ignore --last-delimiter
+    let layout = (config.value_first, config.last_delimiter, config.trim);
+    let (stations, readings, skipped) = if config.count_only.is_some() {
+        match layout {
+            (true, _, false) => count_records::<true, false, false>(data, offset, config)?,
+            (true, _, true) => count_records::<true, false, true>(data, offset, config)?,
+            (false, false, false) => count_records::<false, false, false>(data, offset, config)?,
+            (false, false, true) => count_records::<false, false, true>(data, offset, config)?,
+            (false, true, false) => count_records::<false, true, false>(data, offset, config)?,
+            (false, true, true) => count_records::<false, true, true>(data, offset, config)?,
+        }
+    } else if config.integer_values {
+        match layout {
+            (true, _, false) => sum_records::<true, false, false>(data, offset, config)?,
+            (true, _, true) => sum_records::<true, false, true>(data, offset, config)?,
+            (false, false, false) => sum_records::<false, false, false>(data, offset, config)?,
+            (false, false, true) => sum_records::<false, false, true>(data, offset, config)?,
+            (false, true, false) => sum_records::<false, true, false>(data, offset, config)?,
+            (false, true, true) => sum_records::<false, true, true>(data, offset, config)?,
+        }
+    } else if config.bench_parse_only {
+        return match layout {
+            (true, _, false) => parse_records::<true, false, false>(data, offset, config),
+            (true, _, true) => parse_records::<true, false, true>(data, offset, config),
+            (false, false, false) => parse_records::<false, false, false>(data, offset, config),
+            (false, false, true) => parse_records::<false, false, true>(data, offset, config),
+            (false, true, false) => parse_records::<false, true, false>(data, offset, config),
+            (false, true, true) => parse_records::<false, true, true>(data, offset, config),
+        };
+    } else if let Some(tally) = &context.metric {
+        match config.metric {
+            Some(Metric::PositiveSum) => scan_with_metric(data, offset, config, |values| {
+                merge_partial(&mut tally.lock().unwrap().positive_sum, values)
+            })?,
+            Some(Metric::BelowZero) => scan_with_metric(data, offset, config, |values| {
+                merge_partial(&mut tally.lock().unwrap().below_zero, values)
+            })?,
+            None => reduce_partition(data, offset, config)?,
+        }
+    } else {
+        reduce_partition(data, offset, config)?
+    };
+    if config.strict {
+        check_names_utf8(&stations, data, offset)?;
+    }
+    Ok((stations, readings, skipped))
+}
+
+/// A partition's stations, with the number of readings and the lines skipped.
+type Scanned<'a, R> = Result<(ReducerMap<&'a [u8], R>, usize, Skipped)>;
+
+/// Fold one partition's records into a map of `R`s, in the configured layout.
+fn reduce_partition<'a, R: Reducer>(data: &'a [u8], offset: usize, config: &Config) -> Scanned<'a, R> {
+    match (config.value_first, config.last_delimiter, config.trim) {
+        (true, _, false) => scan_layout::<R, true, false, false>(data, offset, config),
+        (true, _, true) => scan_layout::<R, true, false, true>(data, offset, config),
+        (false, false, false) => scan_layout::<R, false, false, false>(data, offset, config),
+        (false, false, true) => scan_layout::<R, false, false, true>(data, offset, config),
+        (false, true, false) => scan_layout::<R, false, true, false>(data, offset, config),
+        (false, true, true) => scan_layout::<R, false, true, true>(data, offset, config),
+    }
+}
+
+/// Fold a partition's records into each station's stats and an `R` beside
+/// them in one pass, handing the `R`s to `tally`.
+fn scan_with_metric<'a, R: Reducer>(data: &'a [u8], offset: usize, config: &Config,
+                                    tally: impl FnOnce(ReducerMap<&'a [u8], R>)) -> Scanned<'a, WeatherStation> {
+    let (pairs, readings, skipped) = reduce_partition::<(WeatherStation, R)>(data, offset, config)?;
+    let mut stations = StationMap::with_capacity_and_hasher(pairs.len(), StationHasher::from_config(config));
+    let mut values = ReducerMap::with_capacity_and_hasher(pairs.len(), StationHasher::from_config(config));
+    for (name, (station, value)) in pairs {
+        stations.insert(name, station);
+        values.insert(name, value);
+    }
+    tally(values);
+    Ok((stations, readings, skipped))
+}
+
+/// Fail on the first station name that isn't valid UTF-8, reporting where it
+/// first appears. Map keys borrow from `data`, so their position in it is the
+/// offset of the station's first record in this partition.
+fn check_names_utf8(stations: &StationMap<&[u8]>, data: &[u8], offset: usize) -> Result<()> {
+    let invalid = stations
+        .keys()
+        .filter(|name| std::str::from_utf8(name).is_err())
+        .map(|name| (offset + (name.as_ptr() as usize - data.as_ptr() as usize), name))
+        .min();
+    match invalid {
+        Some((at, name)) => bail!("Station name at byte {} is not valid UTF-8: {:?}", at, String::from_utf8_lossy(name)),
+        None => Ok(()),
+    }
+}
+
+/// Malformed lines dropped under `--on-error skip` or `warn`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Skipped {
+    pub count: usize,
+    /// Offsets of the first few, in ascending order.
+    pub offsets: Vec<usize>,
+}
+
+impl Skipped {
+    const MAX_OFFSETS: usize = 5;
+
+    fn record(&mut self, offset: usize) {
+        self.count += 1;
+        if self.offsets.len() < Self::MAX_OFFSETS {
+            self.offsets.push(offset);
+        }
+    }
+
+    pub fn merge(&mut self, other: &Skipped) {
+        self.count += other.count;
+        self.offsets.extend(&other.offsets);
+        self.offsets.sort_unstable();
+        self.offsets.truncate(Self::MAX_OFFSETS);
+    }
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Skipped {} malformed line{}", self.count, if self.count == 1 { "" } else { "s" })?;
+        if let Some((first, rest)) = self.offsets.split_first() {
+            write!(f, ", e.g. at byte {}", first)?;
+            for offset in rest {
+                write!(f, ", {}", offset)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cold]
+fn malformed_record(policy: OnError, skipped: &mut Skipped, offset: usize, line: &[u8]) -> Result<()> {
+    skipped.record(offset);
+    match policy {
+        OnError::Fail => bail!("Malformed record at byte {}: {:?}", offset, String::from_utf8_lossy(line)),
+        OnError::Warn => eprintln!("Skipping malformed record at byte {}: {:?}", offset, String::from_utf8_lossy(line)),
+        OnError::Skip => {}
+    }
+    Ok(())
+}
+
+/// Fail if more than `config.max_stations` distinct stations were found.
+fn check_station_count(stations: usize, config: &Config) -> Result<()> {
+    if stations > config.max_stations {
+        return Err(too_many_stations(stations, config.max_stations));
+    }
+    Ok(())
+}
+
+#[cold]
+fn too_many_stations(found: usize, max: usize) -> anyhow::Error {
+    anyhow!("Found {} distinct stations, more than the limit of {}; \
+             is the input malformed? (raise the limit with --max-stations)", found, max)
+}
+
+#[inline(always)]
+fn scan_records<'a, R: Reducer>(records: impl Iterator<Item = Result<(&'a [u8], Measurement), Malformed<'a>>>, offset: usize,
+                                capacity: usize, config: &Config) -> Scanned<'a, R> {
+    let on_error = config.on_error;
+    let max_stations = config.max_stations;
+    let mut stations = ReducerMap::with_capacity_and_hasher(capacity, StationHasher::from_config(config));
+    let mut num_readings = 0;
+    let mut skipped = Skipped::default();
+    let (mut run_name, mut run, mut in_run): (&[u8], R, _) = (&[], R::default(), false);
+
+    for record in records {
+        let (name, measurement) = match record {
+            Ok(record) => record,
+            Err(malformed) => {
+                malformed_record(on_error, &mut skipped, offset + malformed.offset, malformed.line)?;
+                continue;
+            }
+        };
+        num_readings += 1;
+
+        // Repeats of the previous record's station are accumulated locally
+        // and merged in once the station changes, so runs of one station are
+        // hashed once rather than per record
+        if name == run_name {
+            run.add(measurement);
+            in_run = true;
+            continue;
+        }
+        flush_run(&mut stations, run_name, &mut run, &mut in_run);
+
+        // Store the measurement in the hashmap
+        stations.entry(name).or_default().add(measurement);
+        run_name = name;
+        if stations.len() > max_stations {
+            return Err(too_many_stations(stations.len(), max_stations));
+        }
+    }
+    flush_run(&mut stations, run_name, &mut run, &mut in_run);
+    Ok((stations, num_readings, skipped))
+}
+
+/// Aggregate records in one layout, every `--sample-rate`-th one if sampling.
+fn scan_layout<'a, R: Reducer, const VALUE_FIRST: bool, const LAST_DELIMITER: bool, const TRIM: bool>(
+    data: &'a [u8], offset: usize, config: &Config) -> Scanned<'a, R> {
+    // There can't be more stations than lines, nor more than the limit
+    let capacity = if config.two_pass {
+        distinct_stations::<VALUE_FIRST, LAST_DELIMITER, TRIM>(data, config).min(config.max_stations)
+    } else if config.presize {
+        count_newlines(data).min(config.max_stations)
+    } else {
+        0
+    };
+    let records = RecordIter::<VALUE_FIRST, LAST_DELIMITER, TRIM>::with_layout(data).with_decimals(config.decimals);
+    let sample_rate = config.sample_rate;
+    match (config.assert_sorted_input, sample_rate > 1) {
+        (false, false) => scan_records(records, offset, capacity, config),
+        (false, true) => scan_records(records.step_by(sample_rate), offset, capacity, config),
+        (true, false) => scan_grouped(records, data, offset, capacity, config),
+        (true, true) => scan_grouped(records.step_by(sample_rate), data, offset, capacity, config),
+    }
+}
+
+/// Aggregate records grouped by station, for `--assert-sorted-input`,
+/// inserting each station once its run ends. Fails if a station turns up
+/// again after a different one.
+fn scan_grouped<'a, R: Reducer>(records: impl Iterator<Item = Result<(&'a [u8], Measurement), Malformed<'a>>>, data: &'a [u8],
+                                offset: usize, capacity: usize, config: &Config) -> Scanned<'a, R> {
+    let mut stations = ReducerMap::with_capacity_and_hasher(capacity, StationHasher::from_config(config));
+    let mut num_readings = 0;
+    let mut skipped = Skipped::default();
+    // Names are never empty, so an empty one means no run yet
+    let (mut run_name, mut run): (&[u8], R) = (&[], R::default());
+
+    for record in records {
+        let (name, measurement) = match record {
+            Ok(record) => record,
+            Err(malformed) => {
+                malformed_record(config.on_error, &mut skipped, offset + malformed.offset, malformed.line)?;
+                continue;
+            }
+        };
+        num_readings += 1;
+        if name != run_name {
+            if !run_name.is_empty() {
+                stations.insert(run_name, std::mem::take(&mut run));
+            }
+            if stations.contains_key(name) {
+                let at = offset + (name.as_ptr() as usize - data.as_ptr() as usize);
+                bail!("Input is not grouped by station: {:?} appears again at byte {}", String::from_utf8_lossy(name), at);
+            }
+            if stations.len() >= config.max_stations {
+                return Err(too_many_stations(stations.len() + 1, config.max_stations));
+            }
+            run_name = name;
+        }
+        run.add(measurement);
+    }
+    if !run_name.is_empty() {
+        stations.insert(run_name, run);
+    }
+    Ok((stations, num_readings, skipped))
+}
+
+/// Number of newlines in `data`, counted a vector at a time.
+pub fn count_newlines(data: &[u8]) -> usize {
+    memchr::memchr_iter(b'\n', data).count()
+}
+
+/// Number of distinct station names in `data`, for `--two-pass`.
+///
+/// Lines are split as the record loop splits them, but values aren't parsed,
+/// so a malformed line can add a name the loop won't keep; that only makes
+/// the map a little larger than it needs to be.
+fn distinct_stations<const VALUE_FIRST: bool, const LAST_DELIMITER: bool, const TRIM: bool>(data: &[u8], config: &Config)
+                                                                                          -> usize {
+    let mut names = HashSet::with_hasher(StationHasher::from_config(config));
+    let mut rest = data;
+    while !rest.is_empty() {
+        let line_end = memchr::memchr(b'\n', rest).unwrap_or(rest.len());
+        let (line, tail) = rest.split_at(line_end);
+        rest = tail.get(1..).unwrap_or_default();
+        let line = if TRIM { trim_padding(line) } else { line };
+        let delimiter = if LAST_DELIMITER { memchr::memrchr(b';', line) } else { memchr::memchr(b';', line) };
+        if let Some(delimiter) = delimiter {
+            names.insert(if VALUE_FIRST { &line[delimiter + 1..] } else { &line[..delimiter] });
+        }
+    }
+    names.len()
+}
+
+/// Scan and parse records without aggregating them, for `--bench-parse-only`.
+///
+/// Each parsed record goes through `black_box`, which the optimizer has to
+/// assume reads it, so the parsing can't be elided even though nothing uses
+/// the results.
+fn parse_records<'a, const VALUE_FIRST: bool, const LAST_DELIMITER: bool, const TRIM: bool>(data: &'a [u8], offset: usize, config: &Config) -> Result<(StationMap<&'a [u8]>, usize, Skipped)> {
+    let mut num_readings = 0;
+    let mut skipped = Skipped::default();
+    for record in RecordIter::<VALUE_FIRST, LAST_DELIMITER, TRIM>::with_layout(data).with_decimals(config.decimals) {
+        match record {
+            Ok(record) => {
+                std::hint::black_box(record);
+                num_readings += 1;
+            }
+            Err(malformed) => malformed_record(config.on_error, &mut skipped, offset + malformed.offset, malformed.line)?,
+        }
+    }
+    Ok((StationMap::with_hasher(StationHasher::from_config(config)), num_readings, skipped))
+}
+
+/// Count each station's records without parsing their values, for
+/// `--count-only`. Only the stations' counts mean anything.
+fn count_records<'a, const VALUE_FIRST: bool, const LAST_DELIMITER: bool, const TRIM: bool>(data: &'a [u8], offset: usize, config: &Config) -> Result<(StationMap<&'a [u8]>, usize, Skipped)> {
+    let mut stations = StationMap::with_hasher(StationHasher::from_config(config));
+    let mut num_readings = 0;
+    let mut skipped = Skipped::default();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let line_offset = data.len() - rest.len();
+        let line_end = memchr::memchr(b'\n', rest).unwrap_or(rest.len());
+        let (line, tail) = rest.split_at(line_end);
+        rest = tail.get(1..).unwrap_or_default();
+        let line = if TRIM { trim_padding(line) } else { line };
+
+        let delimiter = if LAST_DELIMITER { memchr::memrchr(b';', line) } else { memchr::memchr(b';', line) };
+        let name = delimiter.map(|delimiter| {
+            if VALUE_FIRST { &line[delimiter + 1..] } else { &line[..delimiter] }
+        });
+        let Some(name) = name.filter(|name| !name.is_empty()) else {
+            malformed_record(config.on_error, &mut skipped, offset + line_offset, line)?;
+            continue;
+        };
+        num_readings += 1;
+        stations.entry(name).or_default().count += 1;
+        if stations.len() > config.max_stations {
+            return Err(too_many_stations(stations.len(), config.max_stations));
+        }
+    }
+    Ok((stations, num_readings, skipped))
+}
+
+/// Total each station's plain integer values, for `--integer-values`, every
+/// `--sample-rate`-th line if sampling. The values go straight into the
+/// stations' `i64` sums, so unlike readings they aren't limited to a
+/// [`Measurement`]; only the sums and counts mean anything.
+fn sum_records<'a, const VALUE_FIRST: bool, const LAST_DELIMITER: bool, const TRIM: bool>(data: &'a [u8], offset: usize, config: &Config) -> Result<(StationMap<&'a [u8]>, usize, Skipped)> {
+    let mut stations = StationMap::with_hasher(StationHasher::from_config(config));
+    let mut num_readings = 0;
+    let mut skipped = Skipped::default();
+    let mut rest = data;
+    let mut lines = 0;
+    while !rest.is_empty() {
+        let line_offset = data.len() - rest.len();
+        let line_end = memchr::memchr(b'\n', rest).unwrap_or(rest.len());
+        let (line, tail) = rest.split_at(line_end);
+        rest = tail.get(1..).unwrap_or_default();
+        lines += 1;
+        if (lines - 1) % config.sample_rate != 0 {
+            continue;
+        }
+        let line = if TRIM { trim_padding(line) } else { line };
+
+        let delimiter = if LAST_DELIMITER { memchr::memrchr(b';', line) } else { memchr::memchr(b';', line) };
+        let record = delimiter.and_then(|delimiter| {
+            let (first, second) = (&line[..delimiter], &line[delimiter + 1..]);
+            let (name, value) = if VALUE_FIRST { (second, first) } else { (first, second) };
+            Some((name, try_parse_integer(value)?)).filter(|(name, _)| !name.is_empty())
+        });
+        let Some((name, value)) = record else {
+            malformed_record(config.on_error, &mut skipped, offset + line_offset, line)?;
+            continue;
+        };
+        num_readings += 1;
+        let station = stations.entry(name).or_default();
+        station.sum += value;
+        station.count += 1;
+        if stations.len() > config.max_stations {
+            return Err(too_many_stations(stations.len(), config.max_stations));
+        }
+    }
+    Ok((stations, num_readings, skipped))
+}
+
+/// Merge the readings accumulated for `name`, which is already in the map,
+/// and reset the run.
+#[inline(always)]
+fn flush_run<R: Reducer>(stations: &mut ReducerMap<&[u8], R>, name: &[u8], run: &mut R, in_run: &mut bool) {
+    if *in_run {
+        stations.get_mut(name).expect("run station is in the map").merge(run);
+        (*run, *in_run) = (R::default(), false);
+    }
+}
+
+/// Diagnostics for a single worker thread.
+///
+/// `start..end` is the span of the file the thread handled. With the mmap
+/// backend that is its whole partition; streaming workers take chunks from a
+/// shared queue, so their spans interleave and `bytes` is what they processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadStats {
+    pub thread: usize,
+    pub start: usize,
+    pub end: usize,
+    pub bytes: usize,
+    pub readings: usize,
+    /// Length in bytes of the longest station name the thread saw.
+    pub longest_name: usize,
+}
+
+impl ThreadStats {
+    fn new(thread: usize) -> Self {
+        ThreadStats { thread, start: 0, end: 0, bytes: 0, readings: 0, longest_name: 0 }
+    }
+
+    /// Account for a chunk of `len` bytes at `start`, widening the span.
+    fn add_chunk(&mut self, start: usize, len: usize, readings: usize) {
+        if self.bytes == 0 {
+            (self.start, self.end) = (start, start + len);
+        } else {
+            (self.start, self.end) = (self.start.min(start), self.end.max(start + len));
+        }
+        self.bytes += len;
+        self.readings += readings;
+    }
+}
+
+#[derive(Debug)]
+pub struct Aggregation {
+    pub stations: StationMap<Vec<u8>>,
+    pub threads: Vec<ThreadStats>,
+    pub skipped: Skipped,
+    /// Estimated memory held by the per-thread maps before they were merged.
+    pub partial_map_bytes: usize,
+    /// CPU time each worker thread used, or empty where threads' CPU time
+    /// can't be measured.
+    pub cpu_times: Vec<Duration>,
+}
+
+impl Aggregation {
+    /// Total readings processed across all threads.
+    pub fn readings(&self) -> usize {
+        self.threads.iter().map(|t| t.readings).sum()
+    }
+
+    /// Length in bytes of the longest station name.
+    pub fn longest_name(&self) -> usize {
+        self.threads.iter().map(|t| t.longest_name).max().unwrap_or(0)
+    }
+
+    /// Check that the merged stations account for every reading the threads
+    /// processed. A mismatch means a partition or merge bug lost or duplicated
+    /// readings.
+    pub fn check_merge(&self) -> Result<()> {
+        let merged: u64 = self.stations.values().map(|s| s.count as u64).sum();
+        if merged != self.readings() as u64 {
+            bail!("Merged stations hold {} readings but {} were processed", merged, self.readings());
+        }
+        Ok(())
+    }
+}
+
+/// Process each partition on its own thread and merge the results.
+///
+/// Partition `i` is always handled by thread `i`, and `threads[i]` describes
+/// the byte range and reading count of that partition, so a slow thread can be
+/// traced back to a specific region of the file.
+///
+/// Given more partitions than `config.threads`, that many workers instead
+/// take the next unclaimed partition whenever they finish one, so a dense
+/// region doesn't hold up the whole run.
+pub fn aggregate(partitions: &[&[u8]], config: &Config) -> Result<Aggregation> {
+    aggregate_with(partitions, config, &RunContext::default())
+}
+
+fn aggregate_with(partitions: &[&[u8]], config: &Config, context: &RunContext) -> Result<Aggregation> {
+    if partitions.len() > config.threads {
+        return aggregate_queued(partitions, config, context);
+    }
+    let results = std::thread::scope(|scope| {
+        let mut offset = 0;
+        let handles: Vec<_> = partitions
+            .iter()
+            .map(|&partition| {
+                let start = offset;
+                offset += partition.len();
+                scope.spawn(move || {
+                    run_partition(partition, start, config, context).map(|result| (result, thread_cpu_time()))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("worker thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let (results, cpu_times): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+
+    let mut threads = Vec::with_capacity(results.len());
+    let mut partials = Vec::with_capacity(results.len());
+    let mut skipped = Skipped::default();
+    let mut partial_map_bytes = 0;
+    let mut start = 0;
+    for (thread, (partial, readings, partial_skipped)) in results.into_iter().enumerate() {
+        let end = start + partitions[thread].len();
+        let longest_name = longest_name(partial.keys());
+        partial_map_bytes += map_bytes(&partial);
+        threads.push(ThreadStats { thread, start, end, bytes: end - start, readings, longest_name });
+        partials.push(partial);
+        skipped.merge(&partial_skipped);
+        start = end;
+    }
+    shuffle_merge_order(&mut partials, config);
+    let stations = merge_partials(partials);
+    check_station_count(stations.len(), config)?;
+    let cpu_times = cpu_times.into_iter().collect::<Option<_>>().unwrap_or_default();
+    Ok(Aggregation { stations, threads, skipped, partial_map_bytes, cpu_times })
+}
+
+fn aggregate_queued(partitions: &[&[u8]], config: &Config, context: &RunContext) -> Result<Aggregation> {
+    let offsets: Vec<usize> = partitions
+        .iter()
+        .scan(0, |offset, partition| {
+            let start = *offset;
+            *offset += partition.len();
+            Some(start)
+        })
+        .collect();
+    let next = AtomicUsize::new(0);
+
+    let results = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..config.threads)
+            .map(|thread| {
+                let (next, offsets) = (&next, &offsets);
+                scope.spawn(move || {
+                    let mut stations = StationMap::with_hasher(StationHasher::from_config(config));
+                    let mut stats = ThreadStats::new(thread);
+                    let mut skipped = Skipped::default();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&partition) = partitions.get(i) else {
+                            break;
+                        };
+                        let start = offsets[i];
+                        let (partial, readings, partial_skipped) = run_partition(partition, start, config, context)
+                            // Stop the other workers claiming more partitions
+                            .inspect_err(|_| next.store(partitions.len(), Ordering::Relaxed))?;
+                        for (name, station) in partial {
+                            stations.entry(name).or_default().merge(&station);
+                        }
+                        stats.add_chunk(start, partition.len(), readings);
+                        skipped.merge(&partial_skipped);
+                    }
+                    stats.longest_name = longest_name(stations.keys());
+                    Ok((stations, stats, skipped, thread_cpu_time()))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("worker thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    let mut threads = Vec::with_capacity(results.len());
+    let mut partials = Vec::with_capacity(results.len());
+    let mut skipped = Skipped::default();
+    let mut partial_map_bytes = 0;
+    let mut cpu_times = Vec::with_capacity(results.len());
+    for (partial, stats, worker_skipped, cpu_time) in results {
+        partial_map_bytes += map_bytes(&partial);
+        threads.push(stats);
+        partials.push(partial);
+        skipped.merge(&worker_skipped);
+        cpu_times.push(cpu_time);
+    }
+    shuffle_merge_order(&mut partials, config);
+    let stations = merge_partials(partials);
+    check_station_count(stations.len(), config)?;
+    let cpu_times = cpu_times.into_iter().collect::<Option<_>>().unwrap_or_default();
+    Ok(Aggregation { stations, threads, skipped, partial_map_bytes, cpu_times })
+}
+
+/// Fold each partition into per-station `R`s on its own thread, through the
+/// same record loop as [`aggregate`], and merge them.
+///
+/// Records are scanned as for the main results, including `--on-error`,
+/// `--sample-rate` and `--assert-sorted-input`, but nothing is scaled up to
+/// make up for sampling.
+pub fn reduce<R: Reducer + Send>(partitions: &[&[u8]], config: &Config) -> Result<ReducerMap<Vec<u8>, R>> {
+    let partials = std::thread::scope(|scope| {
+        let mut offset = 0;
+        let handles: Vec<_> = partitions
+            .iter()
+            .map(|&partition| {
+                let start = offset;
+                offset += partition.len();
+                scope.spawn(move || reduce_partition::<R>(partition, start, config))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("worker thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let mut stations = ReducerMap::with_hasher(StationHasher::from_config(config));
+    for (partial, _, _) in partials {
+        merge_partial(&mut stations, partial);
+    }
+    check_station_count(stations.len(), config)?;
+    Ok(normalize_names(stations, config))
+}
+
+/// CPU time used so far by the calling thread.
+#[cfg(unix)]
+fn thread_cpu_time() -> Option<Duration> {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: `time` is a valid timespec for the call to fill in
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+        return None;
+    }
+    Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+/// Heap memory owned by a map key.
+trait KeyBytes {
+    fn key_bytes(&self) -> usize;
+}
+
+impl KeyBytes for &[u8] {
+    fn key_bytes(&self) -> usize {
+        0
+    }
+}
+
+impl KeyBytes for Vec<u8> {
+    fn key_bytes(&self) -> usize {
+        self.capacity()
+    }
+}
+
+/// Rough memory held by a map: a slot and a control byte for each entry it
+/// has room for, plus any names it owns.
+fn map_bytes<K: KeyBytes>(map: &StationMap<K>) -> usize {
+    let table = map.capacity() * (std::mem::size_of::<(K, WeatherStation)>() + 1);
+    table + map.keys().map(KeyBytes::key_bytes).sum::<usize>()
+}
+
+/// Under `--stats`, the estimated memory of the maps before and after the
+/// merge.
+fn memory_summary(aggregation: &Aggregation) -> String {
+    format!("Station maps: ~{} KiB across threads before merge, ~{} KiB merged ({} stations)",
+            aggregation.partial_map_bytes.div_ceil(1024), map_bytes(&aggregation.stations).div_ceil(1024),
+            aggregation.stations.len())
+}
+
+/// Length of the longest of `names`, found once per station after scanning
+/// rather than on every record.
+fn longest_name<K: AsRef<[u8]>>(names: impl Iterator<Item = K>) -> usize {
+    names.map(|name| name.as_ref().len()).max().unwrap_or(0)
+}
+
+/// Merge the per-thread maps into a single map with owned keys.
+///
+/// A station present in several partials has their stats combined, and one
+/// present in a single partial is moved in as is. Combining is commutative,
+/// so the result doesn't depend on the order of `partials`.
+///
+/// Workers key their maps by names borrowed from the input, so nothing is
+/// allocated per reading. A name is copied into an owned key only the first
+/// time it is seen here, so allocations scale with unique stations rather than
+/// rows.
+pub fn merge_partials(partials: Vec<StationMap<&[u8]>>) -> StationMap<Vec<u8>> {
+    let hasher = partials.first().map(|p| p.hasher().clone()).unwrap_or_default();
+    let mut stations = StationMap::with_hasher(hasher);
+    for partial in partials {
+        merge_partial(&mut stations, partial);
+    }
+    stations
+}
+
+/// Merge one borrowed-key map into an owned-key map, copying names only for
+/// stations not already present.
+fn merge_partial<R: Reducer>(into: &mut ReducerMap<Vec<u8>, R>, partial: ReducerMap<&[u8], R>) {
+    for (name, station) in partial {
+        match into.get_mut(name) {
+            Some(existing) => existing.merge(&station),
+            None => {
+                into.insert(name.to_vec(), station);
+            }
+        }
+    }
+}
+
+/// Shuffle results about to be merged under `--shuffle-merge-order`.
+fn shuffle_merge_order<T>(partials: &mut [T], config: &Config) {
+    if let Some(seed) = config.shuffle_merge_order {
+        Rng::new(seed).shuffle(partials);
+    }
+}
+
+/// Merge one set of owned results into another.
+pub fn merge_stations(into: &mut StationMap<Vec<u8>>, from: StationMap<Vec<u8>>) {
+    for (name, station) in from {
+        into.entry(name).or_default().merge(&station);
+    }
+}
+
+/// Each station's stats in every `merge` input it appears in, by name.
+type Sightings<'a> = BTreeMap<Vec<u8>, Vec<(&'a str, WeatherStation)>>;
+
+fn record_sightings<'a>(sightings: &mut Sightings<'a>, input: &'a str, stations: &StationMap<Vec<u8>>) {
+    for (name, station) in stations {
+        sightings.entry(name.clone()).or_default().push((input, *station));
+    }
+}
+
+/// Write a line for each station seen in more than one input, with its range
+/// and count in each, and whether all of the ranges share some temperature.
+fn write_overlaps(out: &mut impl Write, sightings: &Sightings, config: &Config) -> io::Result<()> {
+    for (name, seen) in sightings.iter().filter(|(_, seen)| seen.len() > 1) {
+        write!(out, "Station {:?} in {} inputs:", String::from_utf8_lossy(name), seen.len())?;
+        for (i, (input, station)) in seen.iter().enumerate() {
+            let (min, max, _) = station.degrees(config.value_decimals());
+            write!(out, "{} {} {} to {} ({})", if i == 0 { "" } else { "," }, input, min, max, station.count)?;
+        }
+        let highest_min = seen.iter().map(|(_, station)| station.min).max().unwrap_or_default();
+        let lowest_max = seen.iter().map(|(_, station)| station.max).min().unwrap_or_default();
+        writeln!(out, "; ranges {}", if highest_min <= lowest_max { "overlap" } else { "are disjoint" })?;
+    }
+    Ok(())
+}
+
+/// Open a measurements file, mmap it and aggregate it across
+/// `config.threads` threads.
+///
+/// A directory is taken to hold shards of one dataset: each file in it whose
+/// name matches `config.glob` is aggregated in turn and the results merged.
+/// An `http://` or `https://` URL is downloaded into memory first.
+pub fn aggregate_file(path: &str, config: &Config) -> Result<Aggregation> {
+    aggregate_file_with(path, config, &RunContext::default())
+}
+
+fn aggregate_file_with(path: &str, config: &Config, context: &RunContext) -> Result<Aggregation> {
+    if Path::new(path).is_dir() {
+        return aggregate_dir(path, config, context);
+    }
+    let started = Instant::now();
+    let aggregation = if http::is_url(path) {
+        aggregate_url(path, config, context)?
+    } else {
+        aggregate_local_file(path, config, context)?
+    };
+    finish_aggregation(aggregation, started, config)
+}
+
+/// Report `--stats` for an aggregation begun at `started`, check its merge,
+/// and scale and normalize its stations as configured.
+fn finish_aggregation(mut aggregation: Aggregation, started: Instant, config: &Config) -> Result<Aggregation> {
+    if config.stats {
+        match config.time_format {
+            TimeFormat::Text => print_stats(&aggregation),
+            TimeFormat::Json => eprintln!("{}", stats_json(&aggregation, started.elapsed())),
+        }
+    }
+    if !config.no_merge_check && !config.bench_parse_only {
+        aggregation.check_merge()?;
+    }
+    if config.sample_rate > 1 {
+        for station in aggregation.stations.values_mut() {
+            station.scale(config.sample_rate as u32);
+        }
+    }
+    aggregation.stations = normalize_names(aggregation.stations, config);
+    Ok(aggregation)
+}
+
+/// Merge stations whose names are the same under `--normalize-space`.
+///
+/// This runs once per station on the merged results rather than per record,
+/// so the record loop keeps borrowing names from the input as they are, and
+/// merging two stations' stats gives what aggregating their readings under
+/// one name would have.
+fn normalize_names<R: Reducer>(stations: ReducerMap<Vec<u8>, R>, config: &Config) -> ReducerMap<Vec<u8>, R> {
+    if !config.normalize_space {
+        return stations;
+    }
+    let mut normalized: ReducerMap<_, R> = ReducerMap::with_capacity_and_hasher(stations.len(), StationHasher::from_config(config));
+    for (mut name, station) in stations {
+        normalize_space(&mut name);
+        normalized.entry(name).or_default().merge(&station);
+    }
+    normalized
+}
+
+/// Collapse runs of ASCII spaces in `name` to one and trim them from both
+/// ends, in place.
+fn normalize_space(name: &mut Vec<u8>) {
+    let mut len = 0;
+    for i in 0..name.len() {
+        if name[i] != b' ' || (len > 0 && name[len - 1] != b' ') {
+            name[len] = name[i];
+            len += 1;
+        }
+    }
+    if len > 0 && name[len - 1] == b' ' {
+        len -= 1;
+    }
+    name.truncate(len);
+}
+
+/// Download a measurements file into memory and aggregate it.
+fn aggregate_url(url: &str, config: &Config, context: &RunContext) -> Result<Aggregation> {
+    let body = http::fetch(url, config).with_context(|| format!("Failed to download {}", url))?;
+    if url.ends_with(".zst") {
+        let measurements = read_zstd(body.as_slice())
+                               .with_context(|| format!("Failed to decompress {}", url))?;
+        return aggregate_slice(&measurements, config, context);
+    }
+    aggregate_slice(&body, config, context)
+}
+
+fn aggregate_local_file(path: &str, config: &Config, context: &RunContext) -> Result<Aggregation> {
+    let measurements_file = File::open(path)
+                                .with_context(|| format!("Failed to open file: {}", path))?;
+    Ok(if path.ends_with(".zst") {
+        // Compressed input can't be mapped, so decompress it into memory
+        let measurements = read_zstd(measurements_file)
+                               .with_context(|| format!("Failed to decompress file: {}", path))?;
+        aggregate_slice(&measurements, config, context)?
+    } else if config.backend == Backend::Stream || over_memory_limit(&measurements_file, config)? {
+        stream::aggregate_stream(&measurements_file, config, context)?
+    } else {
+        let measurements = Contents::load(measurements_file, config)?;
+        aggregate_slice(measurements.as_slice(), config, context)?
+    })
+}
+
+/// Whether mapping the file would go over `--memory-limit`, in which case it
+/// is streamed instead.
+fn over_memory_limit(file: &File, config: &Config) -> Result<bool> {
+    let Some(limit) = config.memory_limit else {
+        return Ok(false);
+    };
+    Ok(file.metadata()?.len() > limit as u64)
+}
+
+/// Partition an in-memory measurements file and aggregate it.
+fn aggregate_slice(data: &[u8], config: &Config, context: &RunContext) -> Result<Aggregation> {
+    let data = match config.first_n_bytes {
+        Some(limit) => record_prefix(data, limit),
+        None => data,
+    };
+    let partitions = try_partition_slice_aligned(data, num_partitions(data.len(), config), config.max_record_len,
+                                                 config.partition_align.bytes())?;
+    if config.print_partitions {
+        print_partitions(&mut io::stderr().lock(), &partitions)?;
+    }
+    aggregate_with(&partitions, config, context)
+}
+
+/// How many partitions to split `len` bytes of input into.
+fn num_partitions(len: usize, config: &Config) -> usize {
+    match (config.partitions, config.chunk_size) {
+        (Some(partitions), _) => partitions,
+        (None, Some(mib)) => len.div_ceil(mib << 20).max(1),
+        (None, None) => config.threads,
+    }
+}
+
+/// Describe what aggregating `input` will do, for `--explain`: how it is
+/// read and split, with the reason for any choice made for the user, the
+/// hasher, how records are parsed, and where the results go.
+fn explain(out: &mut impl Write, input: &str, config: &Config) -> Result<()> {
+    writeln!(out, "Plan for {}:", input)?;
+    // `Some` if the input is read into memory and partitioned, with its
+    // length if that is known before reading it
+    let in_memory = if Path::new(input).is_dir() {
+        writeln!(out, "  input: directory, files matching {}, {} at a time with {} threads each",
+                 config.glob, config.files_in_parallel, config.file_threads())?;
+        None
+    } else if http::is_url(input) {
+        let zstd = if input.ends_with(".zst") { " and decompressed" } else { "" };
+        writeln!(out, "  input: URL, downloaded into memory{}", zstd)?;
+        Some(None)
+    } else if input.ends_with(".zst") {
+        writeln!(out, "  input: compressed file, decompressed into memory")?;
+        Some(None)
+    } else {
+        let file = File::open(input).with_context(|| format!("Failed to open file: {}", input))?;
+        let len = file.metadata()?.len();
+        writeln!(out, "  input: file of {} bytes", len)?;
+        if config.backend == Backend::Stream || over_memory_limit(&file, config)? {
+            let why = if config.backend == Backend::Stream {
+                "--backend stream".to_string()
+            } else {
+                format!("the file is larger than --memory-limit of {} bytes", config.memory_limit.unwrap_or_default())
+            };
+            writeln!(out, "  backend: stream ({}), {} workers and {} readers", why, config.threads, config.io_threads)?;
+            None
+        } else {
+            writeln!(out, "  backend: {}", if config.copy_on_open { "read into memory (--copy-on-open)" } else { "mmap" })?;
+            Some(Some(len as usize))
+        }
+    };
+    if let Some(len) = in_memory {
+        let len = len.map(|len| config.first_n_bytes.map_or(len, |limit| limit.min(len)));
+        let partitions = match (config.partitions, config.chunk_size, len) {
+            (Some(partitions), _, _) => format!("{} (--partitions)", partitions),
+            (None, Some(mib), Some(len)) => format!("{} of up to {} MiB (--chunk-size)", num_partitions(len, config), mib),
+            (None, Some(mib), None) => format!("one per {} MiB (--chunk-size)", mib),
+            (None, None, _) => format!("{}, one per thread", config.threads),
+        };
+        writeln!(out, "  threads: {}, partitions: {}", config.threads, partitions)?;
+    }
+
+    let hasher = if config.deterministic { "SipHash with fixed keys (--deterministic)" } else { "SipHash with random keys" };
+    writeln!(out, "  hasher: {}", hasher)?;
+
+    let mut records = vec![match (config.value_first, config.last_delimiter) {
+        (true, _) => "value;name".to_string(),
+        (false, true) => "name;value, split at the last ;".to_string(),
+        (false, false) => "name;value".to_string(),
+    }];
+    records.push(match config.value_decimals() {
+        0 => "integer values".to_string(),
+        decimals => format!("{} decimal{}", decimals, if decimals == 1 { "" } else { "s" }),
+    });
+    records.extend(config.trim.then(|| "padding trimmed".to_string()));
+    records.extend(config.first_n_bytes.map(|limit| format!("first {} bytes", limit)));
+    records.extend((config.sample_rate > 1).then(|| format!("one in {} sampled", config.sample_rate)));
+    records.extend(config.assert_sorted_input.then(|| "grouped by station".to_string()));
+    records.extend(config.presize.then(|| "maps presized from line counts".to_string()));
+    records.extend(config.two_pass.then(|| "maps sized in a first pass".to_string()));
+    records.extend(config.count_only.is_some().then(|| "counted without parsing values".to_string()));
+    records.extend(config.bench_parse_only.then(|| "parsed without aggregating".to_string()));
+    writeln!(out, "  records: {}, malformed lines {}", records.join(", "), match config.on_error {
+        OnError::Fail => "fail the run",
+        OnError::Warn => "are skipped with a warning",
+        OnError::Skip => "are skipped",
+    })?;
+
+    let format = config.format.to_possible_value().expect("formats are not skipped");
+    let destination = match (&config.output, &config.append_output) {
+        _ if config.no_output => "nowhere (--no-output)".to_string(),
+        (Some(path), _) => path.display().to_string(),
+        (None, Some(path)) => format!("the end of {}", path.display()),
+        (None, None) => "stdout".to_string(),
+    };
+    let places = if config.precision() == 1 { "place" } else { "places" };
+    writeln!(out, "  output: {}, {} decimal {}, to {}", format.get_name(), config.precision(), places, destination)?;
+    Ok(())
+}
+
+/// The complete records within the first `limit` bytes of `data`, for
+/// `--first-n-bytes`.
+fn record_prefix(data: &[u8], limit: usize) -> &[u8] {
+    if limit >= data.len() {
+        return data;
+    }
+    &data[..prev_record_start(data, limit)]
+}
+
+/// Describe each partition's byte range and its first and last records.
+fn print_partitions(out: &mut impl Write, partitions: &[&[u8]]) -> io::Result<()> {
+    let mut start = 0;
+    for (i, partition) in partitions.iter().enumerate() {
+        let end = start + partition.len();
+        write!(out, "Partition {}: bytes {}..{} ({} bytes)", i, start, end, partition.len())?;
+        if !partition.is_empty() {
+            let records = partition.strip_suffix(b"\n").unwrap_or(partition);
+            let first = records.split(|&b| b == b'\n').next().unwrap_or_default();
+            let last = records.rsplit(|&b| b == b'\n').next().unwrap_or_default();
+            write!(out, ", first {:?}, last {:?}", String::from_utf8_lossy(first), String::from_utf8_lossy(last))?;
+        }
+        writeln!(out)?;
+        start = end;
+    }
+    Ok(())
+}
+
+fn aggregate_dir(dir: &str, config: &Config, context: &RunContext) -> Result<Aggregation> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read directory: {}", dir))? {
+        let entry = entry.with_context(|| format!("Failed to read directory: {}", dir))?;
+        if entry.file_type()?.is_file() && config.glob.matches(&entry.file_name().to_string_lossy()) {
+            paths.push(entry.path());
+        }
+    }
+    if paths.is_empty() {
+        bail!("No files in {} match {}", dir, config.glob);
+    }
+    paths.sort();
+
+    let paths = paths
+        .iter()
+        .map(|path| path.to_str().with_context(|| format!("File name is not valid UTF-8: {}", path.display())))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Each file gets its share of the threads, so F files at once with T
+    // threads each never run more than F * T workers
+    let file_config = Config { threads: config.file_threads(), ..config.clone() };
+    let next = AtomicUsize::new(0);
+    let results = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..config.files_in_parallel.min(paths.len()))
+            .map(|_| {
+                let (next, paths, file_config) = (&next, &paths, &file_config);
+                scope.spawn(move || {
+                    let mut aggregations = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&path) = paths.get(i) else {
+                            break;
+                        };
+                        let aggregation = aggregate_file_with(path, file_config, context)
+                            // Stop the other workers claiming more files
+                            .inspect_err(|_| next.store(paths.len(), Ordering::Relaxed))?;
+                        aggregations.push((i, aggregation));
+                    }
+                    Ok(aggregations)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("worker thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let mut results: Vec<_> = results.into_iter().flatten().collect();
+    // Merge in file order whichever worker took each file
+    results.sort_by_key(|&(i, _)| i);
+
+    let mut partials = Vec::with_capacity(results.len());
+    let mut threads = Vec::new();
+    let mut skipped = Skipped::default();
+    let mut partial_map_bytes = 0;
+    let mut cpu_times = Vec::new();
+    for (_, aggregation) in results {
+        partials.push(aggregation.stations);
+        threads.extend(aggregation.threads);
+        skipped.merge(&aggregation.skipped);
+        partial_map_bytes += aggregation.partial_map_bytes;
+        cpu_times.extend(aggregation.cpu_times);
+    }
+    shuffle_merge_order(&mut partials, config);
+    let mut stations = StationMap::with_hasher(StationHasher::from_config(config));
+    for partial in partials {
+        merge_stations(&mut stations, partial);
+    }
+    check_station_count(stations.len(), config)?;
+    Ok(Aggregation { stations, threads, skipped, partial_map_bytes, cpu_times })
+}
+
+/// Decompress a zstd stream into memory, reading the compressed input
+/// incrementally rather than loading it whole.
+fn read_zstd(compressed: impl io::Read) -> Result<Vec<u8>> {
+    let mut decoder = zstd::stream::read::Decoder::new(compressed)?;
+    let mut measurements = Vec::new();
+    io::Read::read_to_end(&mut decoder, &mut measurements)?;
+    Ok(measurements)
+}
+
+fn print_stats(aggregation: &Aggregation) {
+    for t in &aggregation.threads {
+        eprintln!("Thread {}: bytes {}..{} ({} bytes), {} readings, longest name {} bytes",
+                  t.thread, t.start, t.end, t.bytes, t.readings, t.longest_name);
+    }
+    eprintln!("Processed {} readings", aggregation.readings());
+    eprintln!("Longest station name: {} bytes", aggregation.longest_name());
+    eprintln!("{}", memory_summary(aggregation));
+    eprintln!("{}", cpu_summary(&aggregation.cpu_times));
+}
+
+/// Under `--stats`, the spread of CPU time across worker threads. The
+/// imbalance is the slowest thread's time over the median's, so 1.0 means
+/// the work was evenly split.
+fn cpu_summary(cpu_times: &[Duration]) -> String {
+    if cpu_times.is_empty() {
+        return "CPU time per thread: not available".to_string();
+    }
+    let mut sorted = cpu_times.to_vec();
+    sorted.sort_unstable();
+    let (min, median, max) = (sorted[0], sorted[sorted.len() / 2], sorted[sorted.len() - 1]);
+    let imbalance = if median.is_zero() { 1.0 } else { max.as_secs_f64() / median.as_secs_f64() };
+    format!("CPU time across {} threads: min {:.3}s, median {:.3}s, max {:.3}s, imbalance {:.2}x",
+            sorted.len(), min.as_secs_f64(), median.as_secs_f64(), max.as_secs_f64(), imbalance)
+}
+
+/// Summarize a run's timing and throughput as one line of JSON.
+fn stats_json(aggregation: &Aggregation, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let rows = aggregation.readings();
+    let bytes: usize = aggregation.threads.iter().map(|t| t.bytes).sum();
+    // An instant run would otherwise print `inf`, which isn't valid JSON
+    let per_sec = |n: usize| if secs > 0.0 { n as f64 / secs } else { 0.0 };
+    format!("{{\"elapsed_secs\":{:.6},\"rows\":{},\"rows_per_sec\":{:.1},\"bytes\":{},\"bytes_per_sec\":{:.1},\"threads\":{}}}",
+            secs, rows, per_sec(rows), bytes, per_sec(bytes), aggregation.threads.len())
+}
+
+/// Write the results, under `--checksum` also printing a hash of exactly the
+/// bytes written. Nothing is written under `--no-output`.
+fn emit_results(out: &mut impl Write, stations: &StationMap<Vec<u8>>, metric: Option<&MetricColumn>, config: &Config)
+                -> Result<()> {
+    if config.no_output {
+        return Ok(());
+    }
+    let names = listed_stations(config)?;
+    let write = |mut out: &mut dyn Write| write_output(&mut out, stations, names.as_deref(), metric, config);
+    if !config.checksum {
+        return Ok(write(out)?);
+    }
+    let mut hashing = HashingWriter { inner: out, hasher: Sha256::new() };
+    write(&mut hashing)?;
+    eprintln!("Checksum: {}", hex(&hashing.hasher.finalize()));
+    Ok(())
+}
+
+/// The `--metric` column from the values tallied while aggregating.
+fn metric_column(context: RunContext, config: &Config) -> Option<MetricColumn> {
+    let (metric, tally) = config.metric.zip(context.metric)?;
+    let tally = tally.into_inner().unwrap();
+    let values = match metric {
+        Metric::PositiveSum => normalize_names(tally.positive_sum, config)
+            .into_iter()
+            .map(|(name, sum)| {
+                let sum = Rounded::new(sum.0, 1).with_mode(config.round_mode).with_decimals(config.decimals);
+                (name, format!("{:.*}", config.precision(), sum))
+            })
+            .collect(),
+        Metric::BelowZero => normalize_names(tally.below_zero, config)
+            .into_iter()
+            .map(|(name, below)| (name, below.0.to_string()))
+            .collect(),
+    };
+    Some(MetricColumn { name: metric.name(), values })
+}
+
+/// The station names listed in `--stations-file`, one per line, skipping
+/// blank lines.
+fn listed_stations(config: &Config) -> Result<Option<Vec<Vec<u8>>>> {
+    let Some(path) = &config.stations_file else {
+        return Ok(None);
+    };
+    let list = std::fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let names = list
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut name = line.to_vec();
+            if config.normalize_space {
+                normalize_space(&mut name);
+            }
+            name
+        })
+        .collect();
+    Ok(Some(names))
+}
+
+/// Passes writes on to `inner`, hashing the bytes written for `--checksum`
+/// rather than holding the whole output to hash it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The stations saved in the `--resume` file, with the length of input they
+/// already cover if it is a checkpoint.
+fn resumed_state(config: &Config) -> Result<(StationMap<Vec<u8>>, usize)> {
+    match &config.resume {
+        Some(path) => {
+            let path = path.to_str().with_context(|| format!("File name is not valid UTF-8: {}", path.display()))?;
+            state::read_checkpoint_file(path, config)
+        }
+        None => Ok((StationMap::with_hasher(StationHasher::from_config(config)), 0)),
+    }
+}
+
+fn save_state(stations: &StationMap<Vec<u8>>, config: &Config) -> Result<()> {
+    match &config.merge_output {
+        Some(path) => state::write_state_file(path, stations),
+        None => Ok(()),
+    }
+}
+
+/// Stations with fewer than `threshold` readings, sorted by name.
+fn rare_stations(stations: &StationMap<Vec<u8>>, threshold: u32) -> Vec<(&[u8], u32)> {
+    let mut rare: Vec<_> = stations
+        .iter()
+        .filter(|(_, station)| station.count < threshold)
+        .map(|(name, station)| (name.as_slice(), station.count))
+        .collect();
+    rare.sort_unstable();
+    rare
+}
+
+/// Note on stderr when `--timeout` cut the run short, as the results then
+/// cover only the records read before it.
+fn warn_timed_out(config: &Config, context: &RunContext) {
+    if let (Some(timeout), Some(deadline)) = (config.timeout, &context.deadline) {
+        if deadline.fired() {
+            eprintln!("Timed out after {:?}: results cover only part of the input, so counts are incomplete", timeout);
+        }
+    }
+}
+
+fn warn_rare(stations: &StationMap<Vec<u8>>, config: &Config) {
+    let Some(threshold) = config.warn_rare else {
+        return;
+    };
+    for (name, count) in rare_stations(stations, threshold) {
+        eprintln!("Rare station {:?}: {} reading{}", String::from_utf8_lossy(name), count, if count == 1 { "" } else { "s" });
+    }
+}
+
+/// Stations whose readings span more than `max_span` tenths of a degree,
+/// with their span, sorted by name.
+// The casts only widen when `Measurement` is i16
+#[allow(clippy::unnecessary_cast)]
+fn wide_stations(stations: &StationMap<Vec<u8>>, max_span: i32) -> Vec<(&[u8], i32)> {
+    let mut wide: Vec<_> = stations
+        .iter()
+        .map(|(name, station)| (name.as_slice(), station.max as i32 - station.min as i32))
+        .filter(|&(_, span)| span > max_span)
+        .collect();
+    wide.sort_unstable();
+    wide
+}
+
+fn warn_wide(stations: &StationMap<Vec<u8>>, config: &Config) {
+    // Counting alone leaves no min or max to check
+    let (Some(degrees), None) = (config.max_span, config.count_only) else {
+        return;
+    };
+    let unit = 10f64.powi(config.decimals as i32);
+    for (name, span) in wide_stations(stations, (degrees * unit).round() as i32) {
+        let (min, max, _) = stations[name].degrees(config.decimals);
+        eprintln!("Implausible span for station {:?}: {} to {}, {:.*} degrees",
+                  String::from_utf8_lossy(name), min, max, config.decimals, span as f64 / unit);
+    }
+}
+
+fn report_skipped(skipped: &Skipped, config: &Config) {
+    if config.report_errors {
+        eprintln!("{}", skipped);
+    }
+}
+
+/// Fail if the number of readings processed differs from the expected count.
+fn check_expected_rows(readings: usize, expected: Option<usize>) -> Result<()> {
+    match expected {
+        Some(expected) if expected != readings => {
+            bail!("Expected {} readings but processed {}", expected, readings)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn open_output(config: &Config) -> Result<Box<dyn Write>> {
+    Ok(match (&config.output, &config.append_output) {
+        (Some(path), _) => {
+            let file = File::create(path)
+                           .with_context(|| format!("Failed to create file: {}", path.display()))?;
+            Box::new(BufWriter::new(file))
+        }
+        (None, Some(path)) => {
+            let file = OpenOptions::new().append(true).create(true).open(path)
+                           .with_context(|| format!("Failed to open file: {}", path.display()))?;
+            Box::new(BufWriter::new(file))
+        }
+        (None, None) => Box::new(BufWriter::new(io::stdout().lock())),
+    })
+}
+
+/// Under `--append-output`, start a block of results with a line giving the
+/// time, what was aggregated and the readings processed.
+fn write_block_header(out: &mut impl Write, input: &str, readings: usize, config: &Config) -> Result<()> {
+    if config.append_output.is_some() && !config.no_output {
+        writeln!(out, "# {} {} rows={}", utc_timestamp(SystemTime::now()), input, readings)?;
+    }
+    Ok(())
+}
+
+/// `time` as an RFC 3339 UTC timestamp to the second.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Days since the epoch to a proleptic Gregorian date, counting years
+    // from March so the leap day ends them
+    let z = days + 719468;
+    let (era, doe) = (z / 146097, z % 146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Run the command selected on the command line.
+pub fn run(cli: &Cli) -> Result<()> {
+    let config = &cli.config;
+    let context = RunContext {
+        deadline: config.timeout.map(Deadline::after),
+        metric: config.metric.map(|_| Mutex::default()),
+    };
+    if config.append_output.is_some()
+        && matches!(cli.command, Some(Command::Generate(_) | Command::Verify { .. } | Command::Compare { .. }))
+    {
+        bail!("--append-output only applies when writing results");
+    }
+    if config.report_overlaps && !matches!(cli.command, Some(Command::Merge { .. })) {
+        bail!("--report-overlaps only applies to the merge subcommand");
+    }
+    if config.checkpoint_interval.is_some() && cli.command.is_some() {
+        bail!("--checkpoint-interval only applies when aggregating without a subcommand");
+    }
+    if config.metric.is_some() && matches!(cli.command, Some(Command::Merge { .. } | Command::Verify { .. })) {
+        bail!("--metric only applies when aggregating without a subcommand");
+    }
+    let mut out = open_output(config)?;
+    match &cli.command {
+        None => {
+            if config.metric.is_some() && matches!(config.format, Format::Official | Format::Raw) {
+                bail!("--metric is only shown in the verbose, table and CSV formats");
+            }
+            if config.explain {
+                explain(&mut io::stderr().lock(), &config.input, config)?;
+            }
+            let (mut stations, covered) = resumed_state(config)?;
+            let aggregation = if config.checkpoint_interval.is_some() || covered > 0 {
+                checkpoint::aggregate_file_checkpointed(&config.input, covered, &stations, config, &context)?
+            } else {
+                aggregate_file_with(&config.input, config, &context)?
+            };
+            let readings = aggregation.readings();
+            warn_timed_out(config, &context);
+            report_skipped(&aggregation.skipped, config);
+            check_expected_rows(readings, config.expect_rows)?;
+            merge_stations(&mut stations, aggregation.stations);
+            check_station_count(stations.len(), config)?;
+            save_state(&stations, config)?;
+            warn_rare(&stations, config);
+            warn_wide(&stations, config);
+            let metric = metric_column(context, config);
+            write_block_header(&mut out, &config.input, readings, config)?;
+            emit_results(&mut out, &stations, metric.as_ref(), config)?;
+        }
+        Some(Command::Generate(args)) => generate(args, &mut out)?,
+        Some(Command::Compare { before, after }) => compare::compare(&mut out, before, after, config)?,
+        Some(Command::Merge { inputs }) => {
+            let (mut stations, _) = resumed_state(config)?;
+            let mut readings = 0;
+            let mut skipped = Skipped::default();
+            let mut sightings = Sightings::new();
+            for input in inputs {
+                if state::is_state_file(input)? {
+                    if config.explain {
+                        eprintln!("Plan for {}: read saved state", input);
+                    }
+                    let saved = state::read_state_file(input, config)?;
+                    readings += saved.values().map(|s| s.count as usize).sum::<usize>();
+                    if config.report_overlaps {
+                        record_sightings(&mut sightings, input, &saved);
+                    }
+                    merge_stations(&mut stations, saved);
+                    check_station_count(stations.len(), config)?;
+                    continue;
+                }
+                if config.explain {
+                    explain(&mut io::stderr().lock(), input, config)?;
+                }
+                let aggregation = aggregate_file_with(input, config, &context)?;
+                readings += aggregation.readings();
+                skipped.merge(&aggregation.skipped);
+                if config.report_overlaps {
+                    record_sightings(&mut sightings, input, &aggregation.stations);
+                }
+                merge_stations(&mut stations, aggregation.stations);
+                check_station_count(stations.len(), config)?;
+            }
+            warn_timed_out(config, &context);
+            report_skipped(&skipped, config);
+            if config.report_overlaps {
+                write_overlaps(&mut io::stderr().lock(), &sightings, config)?;
+            }
+            check_expected_rows(readings, config.expect_rows)?;
+            save_state(&stations, config)?;
+            warn_rare(&stations, config);
+            warn_wide(&stations, config);
+            write_block_header(&mut out, &inputs.join(" "), readings, config)?;
+            emit_results(&mut out, &stations, None, config)?;
+        }
+        Some(Command::Verify { input, expected }) => {
+            if config.explain {
+                explain(&mut io::stderr().lock(), input, config)?;
+            }
+            let aggregation = aggregate_file_with(input, config, &context)?;
+            warn_timed_out(config, &context);
+            report_skipped(&aggregation.skipped, config);
+            check_expected_rows(aggregation.readings(), config.expect_rows)?;
+            let mut actual = Vec::new();
+            write_results(&mut actual, &aggregation.stations, config)?;
+            let expected_bytes = std::fs::read(expected)
+                                     .with_context(|| format!("Failed to read file: {}", expected.display()))?;
+            verify_output(&actual, &expected_bytes)
+                .with_context(|| format!("Output does not match {}", expected.display()))?;
+            writeln!(out, "OK: output matches {}", expected.display())?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Compare produced output to the expected output line by line.
+fn verify_output(actual: &[u8], expected: &[u8]) -> Result<()> {
+    let trim = |s: &[u8]| s.strip_suffix(b"\n").unwrap_or(s).to_vec();
+    let (actual, expected) = (trim(actual), trim(expected));
+    let mut actual_lines = actual.split(|&b| b == b'\n');
+    let mut expected_lines = expected.split(|&b| b == b'\n');
+    for line in 1.. {
+        match (actual_lines.next(), expected_lines.next()) {
+            (None, None) => return Ok(()),
+            (a, e) if a == e => continue,
+            (a, e) => bail!("Line {}: expected {:?}, got {:?}", line,
+                            String::from_utf8_lossy(e.unwrap_or_default()),
+                            String::from_utf8_lossy(a.unwrap_or_default())),
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::collections::HashMap;
+
+    /// Counts allocations made by the current thread, so tests running in
+    /// parallel don't disturb each other's counts.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|count| count.get())
+    }
+
+    /// Write `contents` to a file unique to this test process.
+    fn fixture(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rs-1brc-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_weather_station() {
+        let mut ws = WeatherStation::new();
+        ws.add_measurement(100);
+        ws.add_measurement(50);
+        ws.add_measurement(150);
+        assert_eq!(ws.min(), 5.0);
+        assert_eq!(ws.max(), 15.0);
+        assert_eq!(ws.mean(), 10.0);
+    }
+
+    #[test]
+    fn test_weather_station_constructors() {
+        let empty = WeatherStation::default();
+        assert_eq!(empty, WeatherStation::new());
+        assert_eq!(empty.count(), 0);
+
+        let single = WeatherStation::from_single(-42);
+        let mut added = WeatherStation::new();
+        added.add_measurement(-42);
+        assert_eq!(single, added);
+        assert_eq!((single.min_raw(), single.max_raw(), single.sum_raw(), single.count()), (-42, -42, -42, 1));
+
+        // Merging into an empty station leaves the other unchanged
+        let mut merged = WeatherStation::default();
+        merged.merge(&single);
+        assert_eq!(merged, single);
+    }
+
+    #[test]
+    fn test_weather_station_merge() {
+        let mut ws1 = WeatherStation::new();
+        ws1.add_measurement(100);
+        ws1.add_measurement(50);
+        ws1.add_measurement(150);
+        let mut ws2 = WeatherStation::new();
+        ws2.add_measurement(200);
+        ws2.add_measurement(250);
+        ws2.add_measurement(300);
+        ws1.merge(&ws2);
+        assert_eq!(ws1.min(), 5.0);
+        assert_eq!(ws1.max(), 30.0);
+        assert_eq!(ws1.mean(), 17.5);
+    }
+
+    #[test]
+    fn test_weather_station_raw_accessors() {
+        let mut ws = WeatherStation::new();
+        ws.add_measurement(-123);
+        ws.add_measurement(456);
+        assert_eq!((ws.min_raw(), ws.max_raw(), ws.sum_raw(), ws.count()), (-123, 456, 333, 2));
+        assert_eq!(ws.min(), ws.min_raw() as f32 / 10.0);
+        assert_eq!(ws.max(), ws.max_raw() as f32 / 10.0);
+    }
+
+    #[test]
+    fn test_weather_station_display() {
+        let mut ws = WeatherStation::new();
+        ws.add_measurement(-123);
+        ws.add_measurement(456);
+        ws.add_measurement(12);
+        assert_eq!(ws.to_string(), "-12.3/11.5/45.6");
+        assert_eq!(format!("{:.2}", ws), "-12.30/11.50/45.60");
+        assert_eq!(format!("{:.0}", ws), "-12/12/46");
+        assert_eq!(format!("{:.1}", ws.rounded(RoundMode::Trunc, 2)), "-1.2/1.1/4.5");
+
+        // A mean of exactly 1.25 rounds half up
+        let mut ws = WeatherStation::new();
+        ws.add_measurement(12);
+        ws.add_measurement(13);
+        assert_eq!(ws.rounded_mean().to_string(), "1.3");
+        let mut ws = WeatherStation::new();
+        ws.add_measurement(-12);
+        ws.add_measurement(-13);
+        assert_eq!(ws.rounded_mean().to_string(), "-1.2");
+    }
+
+    #[test]
+    fn test_round_modes() {
+        // True means of 1.25, -1.25, 1.35 and 1.26, and a whole number
+        let means = [(25, 2), (-25, 2), (27, 2), (63, 5), (30, 3)].map(|(tenths, count)| Rounded::new(tenths, count));
+        for (mode, expected) in [
+            (RoundMode::Ieee, ["1.2", "-1.2", "1.4", "1.3", "1.0"]),
+            (RoundMode::HalfUp, ["1.3", "-1.2", "1.4", "1.3", "1.0"]),
+            (RoundMode::Trunc, ["1.2", "-1.2", "1.3", "1.2", "1.0"]),
+        ] {
+            assert_eq!(means.map(|mean| mean.with_mode(mode).to_string()), expected, "{:?}", mode);
+        }
+        assert_eq!(format!("{:.0}", Rounded::new(25, 1).with_mode(RoundMode::Ieee)), "2");
+        assert_eq!(format!("{:.0}", Rounded::new(-35, 1).with_mode(RoundMode::Ieee)), "-4");
+        assert_eq!(format!("{:.0}", Rounded::new(-39, 1).with_mode(RoundMode::Trunc)), "-3");
+        assert_eq!(format!("{:.2}", Rounded::new(1, 3).with_mode(RoundMode::Trunc)), "0.03");
+    }
+
+    #[test]
+    fn test_parse_measurement_with_decimal() {
+        let measurement = b"123.4";
+        assert_eq!(parse_measurement(measurement), 1234);
+    }
+
+    #[test]
+    fn test_parse_measurement_negative_with_decimal() {
+        let measurement = b"-123.4";
+        assert_eq!(parse_measurement(measurement), -1234);
+    }
+
+    #[test]
+    fn test_value_first_matches_name_first() {
+        let (name_first, _) = thread_runner(b"Berlin;10.1\n", &Config::default()).unwrap();
+        let config = Config { value_first: true, ..Config::default() };
+        let (value_first, _) = thread_runner(b"10.1;Berlin\n", &config).unwrap();
+        let (a, b) = (&name_first[&b"Berlin"[..]], &value_first[&b"Berlin"[..]]);
+        assert_eq!(value_first.len(), 1);
+        assert_eq!((a.min, a.max, a.sum, a.count), (b.min, b.max, b.sum, b.count));
+        assert_eq!(b.sum, 101);
+    }
+
+    #[test]
+    fn test_last_delimiter() {
+        let data = b"A;B;10.0\nA;B;-2.0\nC;1.0\n";
+        assert!(thread_runner(data, &Config::default()).is_err());
+        let config = Config { last_delimiter: true, ..Config::default() };
+        let (stations, readings) = thread_runner(data, &config).unwrap();
+        assert_eq!(readings, 3);
+        assert_eq!(stations[&b"A;B"[..]], WeatherStation { min: -20, max: 100, sum: 80, count: 2 });
+        assert_eq!(stations[&b"C"[..]].count(), 1);
+
+        // The value after the last delimiter must still parse
+        assert!(thread_runner(b"A;B;oops\n", &config).is_err());
+    }
+
+    #[test]
+    fn test_trim_fixed_width_padding() {
+        let data = b"Berlin;10.1\0\0\nBerlin;-0.5  \n";
+        assert!(thread_runner(data, &Config::default()).is_err());
+        let config = Config { trim: true, ..Config::default() };
+        let (stations, readings) = thread_runner(data, &config).unwrap();
+        assert_eq!(readings, 2);
+        assert_eq!(stations[&b"Berlin"[..]], WeatherStation { min: -5, max: 101, sum: 96, count: 2 });
+
+        // Padding after a value-first name isn't part of it, whatever the loop
+        let data = b"10.1;Berlin\0\0\n";
+        for config in [Config { value_first: true, trim: true, ..Config::default() },
+                       Config { value_first: true, trim: true, count_only: Some(CountOrder::Name), ..Config::default() }] {
+            let (stations, _) = thread_runner(data, &config).unwrap();
+            assert_eq!(stations.keys().collect::<Vec<_>>(), [&&b"Berlin"[..]]);
+        }
+    }
+
+    #[test]
+    fn test_value_first_rejects_name_first_records() {
+        let config = Config { value_first: true, ..Config::default() };
+        assert!(thread_runner(b"Berlin;10.1\n", &config).is_err());
+        assert!(thread_runner(b"10.1;\n", &config).is_err());
+    }
+
+    #[test]
+    fn test_on_error_policies() {
+        let data = b"Berlin;10.1\nnot a record\nBerlin;20.1\n";
+
+        let err = thread_runner(data, &Config::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Malformed record at byte 12: \"not a record\"");
+
+        for on_error in [OnError::Skip, OnError::Warn] {
+            let config = Config { on_error, ..Config::default() };
+            let (stations, readings) = thread_runner(data, &config).unwrap();
+            assert_eq!(readings, 2);
+            assert_eq!(stations[&b"Berlin"[..]].count, 2);
+        }
+    }
+
+    #[test]
+    fn test_malformed_record_location_is_file_offset() {
+        let partitions: [&[u8]; 2] = [b"A;1.0\n", b"B;2.0\nB;oops\n"];
+        let err = aggregate(&partitions, &Config::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Malformed record at byte 12: \"B;oops\"");
+    }
+
+    #[test]
+    fn test_strict_rejects_invalid_utf8_names() {
+        let partitions: [&[u8]; 2] = [b"Berlin;1.0\n", b"Hamburg;2.0\nBad\xff;3.0\n"];
+        assert!(aggregate(&partitions, &Config::default()).is_ok());
+
+        let config = Config { strict: true, ..Config::default() };
+        let err = aggregate(&partitions, &config).unwrap_err();
+        assert_eq!(err.to_string(), "Station name at byte 23 is not valid UTF-8: \"Bad\u{fffd}\"");
+        let config = Config { count_only: Some(CountOrder::Name), ..config };
+        assert!(aggregate(&partitions, &config).is_err());
+    }
+
+    #[test]
+    fn test_lenient_names_keep_raw_bytes() {
+        // Both names print as "Bad\u{fffd}", but are different stations
+        let data: &[u8] = b"Bad\xff;1.0\nBad\xfe;2.0\nBad\xff;3.0\n";
+        let stations = aggregate(&partition_slice(data, 2), &Config::default()).unwrap().stations;
+        assert_eq!(stations.len(), 2);
+        assert_eq!(stations[&b"Bad\xff"[..]].count(), 2);
+        assert_eq!(stations[&b"Bad\xfe"[..]].count(), 1);
+
+        let mut out = Vec::new();
+        write_results(&mut out, &stations, &Config { no_count: true, ..Config::default() }).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Bad\u{fffd}: min=2 max=2 mean=2.0\nBad\u{fffd}: min=1 max=3 mean=2.0\n");
+    }
+
+    #[test]
+    fn test_try_parse_measurement() {
+        assert_eq!(try_parse_measurement(b"12.3"), Some(123));
+        assert_eq!(try_parse_measurement(b"-0.5"), Some(-5));
+        assert_eq!(try_parse_measurement(b"-999.9"), Some(-9999));
+        // Would wrap around in an i16
+        assert_eq!(try_parse_measurement(b"12345.6"), None);
+        assert!(thread_runner(b"A;12345.6\n", &Config::default()).is_err());
+        for bad in [&b""[..], b"-", b"1", b"1.", b".5", b"1.23", b"1.2.3", b"a.b", b"--1.0", b"1000.0"] {
+            assert_eq!(try_parse_measurement(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_measurement_matches_scalar() {
+        for int_digits in 1..=MAX_INTEGER_DIGITS {
+            for tenths in 0..10 * Measurement::pow(10, int_digits as u32) {
+                for sign in ["", "-"] {
+                    let value = format!("{}{:0width$}.{}", sign, tenths / 10, tenths % 10, width = int_digits);
+                    let value = value.as_bytes();
+                    assert_eq!(parse_measurement(value), parse_measurement_scalar(value), "{:?}", value);
+                    assert_eq!(try_parse_measurement(value).map(Measurement::abs), Some(tenths));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_measurement_garbage_does_not_panic() {
+        // Too long for the word parser, so these take the scalar path, whose
+        // arithmetic would overflow or underflow if it weren't wrapping
+        for value in [&b"99999999999999.9"[..], b"-99999999999999.9", b"!!!!!!!!!!!!", b"-  ,,\x00\x00\xff\xff\xff"] {
+            parse_measurement(value);
+            assert_eq!(try_parse_measurement(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_try_parse_decimals() {
+        assert_eq!(try_parse_decimals(b"10.12", 2), Some(1012));
+        assert_eq!(try_parse_decimals(b"-0.05", 2), Some(-5));
+        assert_eq!(try_parse_decimals(b"99.99", 2), Some(9999));
+        assert_eq!(try_parse_decimals(b"-1.234", 3), Some(-1234));
+        for (bad, decimals) in [(&b"10.1"[..], 2), (b"10.123", 2), (b"100.12", 2), (b"10.12", 3), (b".12", 2), (b"1,12", 2),
+                                (b"1.2a", 2), (b"", 2), (b"-", 3)] {
+            assert_eq!(try_parse_decimals(bad, decimals), None, "{:?} with {}", bad, decimals);
+        }
+        for value in [&b"0.0"[..], b"-12.3", b"999.9", b"1.23", b"1000.0", b"1."] {
+            assert_eq!(try_parse_decimals(value, 1), try_parse_measurement(value), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_try_parse_integer() {
+        assert_eq!(try_parse_integer(b"42"), Some(42));
+        assert_eq!(try_parse_integer(b"-7"), Some(-7));
+        assert_eq!(try_parse_integer(b"007"), Some(7));
+        assert_eq!(try_parse_integer(b"40000"), Some(40000));
+        assert_eq!(try_parse_integer(b"-999999999999999999"), Some(-999_999_999_999_999_999));
+        for bad in [&b""[..], b"-", b"1.0", b"4 2", b"+1", b"--1", b"1000000000000000000"] {
+            assert_eq!(try_parse_integer(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_integer_values() {
+        // Counts well past what a reading holds go straight into the sums
+        let input = fixture("integer-values", b"apples;3\npears;40000\napples;-1\napples;250\npears;9000000000\n");
+        let output = fixture("integer-values-out", b"");
+        let config = Config { input: input.to_str().unwrap().to_string(), output: Some(output.clone()), integer_values: true,
+                              ..Config::default() };
+        run(&Cli { command: None, config: config.clone() }).unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "apples: sum=252 count=3\npears: sum=9000040000 count=2\n");
+
+        // Temperatures aren't integers, and integers aren't temperatures
+        assert!(thread_runner(b"apples;3\n", &Config::default()).is_err());
+        assert!(thread_runner(b"Oslo;3.0\n", &config).is_err());
+        for path in [input, output] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_decimals_scale_the_results() {
+        let data = b"Berlin;10.12\nBerlin;-0.05\nBerlin;3.00\nOslo;0.01\n";
+        assert!(thread_runner(data, &Config::default()).is_err());
+        let config = Config { decimals: 2, ..Config::default() };
+        let stations = merge_partials(vec![thread_runner(data, &config).unwrap().0]);
+        assert_eq!(stations[&b"Berlin"[..]], WeatherStation { min: -5, max: 1012, sum: 1307, count: 3 });
+
+        let render = |config: &Config| {
+            let mut out = Vec::new();
+            write_results(&mut out, &stations, config).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(render(&Config { format: Format::Official, ..config.clone() }), "{Berlin=-0.05/4.36/10.12, Oslo=0.01/0.01/0.01}\n");
+        assert_eq!(render(&Config { format: Format::Official, output_precision: Some(1), ..config.clone() }),
+                   "{Berlin=0.0/4.4/10.1, Oslo=0.0/0.0/0.0}\n");
+        assert_eq!(render(&config), "Berlin: min=-0.05 max=10.12 mean=4.36 count=3\nOslo: min=0.01 max=0.01 mean=0.01 count=1\n");
+    }
+
+    #[test]
+    fn test_empty_and_sign_only_values() {
+        assert_eq!(parse_measurement(b""), 0);
+        assert_eq!(parse_measurement(b"-"), 0);
+
+        let err = thread_runner(b"Berlin;\n", &Config::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Malformed record at byte 0: \"Berlin;\"");
+        let err = thread_runner(b"Berlin;-\n", &Config::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Malformed record at byte 0: \"Berlin;-\"");
+
+        let config = Config { on_error: OnError::Skip, ..Config::default() };
+        let (stations, readings) = thread_runner(b"Berlin;\nBerlin;-\nBerlin;1.5\n", &config).unwrap();
+        assert_eq!((readings, stations[&b"Berlin"[..]].count()), (1, 1));
+    }
+
+    #[test]
+    fn test_partition_assignment_is_stable() {
+        let partitions: [&[u8]; 3] = [b"A;1.0\n", b"B;2.0\nB;3.0\n", b"C;4.0\nC;5.0\nC;6.0\n"];
+        let first = aggregate(&partitions, &Config::default()).unwrap();
+        assert_eq!(first.threads, vec![
+            ThreadStats { thread: 0, start: 0, end: 6, bytes: 6, readings: 1, longest_name: 1 },
+            ThreadStats { thread: 1, start: 6, end: 18, bytes: 12, readings: 2, longest_name: 1 },
+            ThreadStats { thread: 2, start: 18, end: 36, bytes: 18, readings: 3, longest_name: 1 },
+        ]);
+        for _ in 0..10 {
+            assert_eq!(aggregate(&partitions, &Config::default()).unwrap().threads, first.threads);
+        }
+        assert_eq!(first.stations[&b"C"[..]].count, 3);
+    }
+
+    #[test]
+    fn test_merge_allocates_per_unique_station() {
+        let chunks: Vec<Vec<u8>> = (0..4)
+            .map(|_| ["Berlin;1.0\n", "Hamburg;2.0\n", "Munich;3.0\n"].concat().repeat(2_500).into_bytes())
+            .collect();
+        let partials: Vec<_> = chunks
+            .iter()
+            .map(|chunk| thread_runner(chunk, &Config::default()).unwrap().0)
+            .collect();
+
+        let before = allocations();
+        let merged = merge_partials(partials);
+        let merge_allocations = allocations() - before;
+
+        assert_eq!(merged.len(), 3);
+        // One key per unique station plus a few for growing the table, no
+        // matter how many rows or partials went in
+        assert!(merge_allocations <= 3 + 4, "{} allocations", merge_allocations);
+    }
+
+    #[test]
+    fn test_mapping_len() {
+        assert_eq!(mapping_len(0).unwrap(), 0);
+        assert_eq!(mapping_len(u32::MAX as u64).unwrap(), u32::MAX as usize);
+        if usize::BITS < u64::BITS {
+            let err = mapping_len(u64::MAX).unwrap_err();
+            assert!(err.to_string().contains("too large to map"));
+        } else {
+            assert_eq!(mapping_len(u64::MAX).unwrap(), usize::MAX);
+        }
+    }
+
+    #[test]
+    fn test_official_format() {
+        let partials = vec![thread_runner(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;34.2\n", &Config::default()).unwrap().0];
+        let mut out = Vec::new();
+        let config = Config { format: Format::Official, ..Config::default() };
+        write_results(&mut out, &merge_partials(partials), &config).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "{Bulawayo=8.9/8.9/8.9, Hamburg=12.0/23.1/34.2}\n");
+    }
+
+    #[test]
+    fn test_verify_output() {
+        assert!(verify_output(b"a\nb\n", b"a\nb").is_ok());
+        let err = verify_output(b"a\nb\n", b"a\nc\n").unwrap_err();
+        assert!(err.to_string().starts_with("Line 2:"), "{}", err);
+        assert!(verify_output(b"a\n", b"a\nb\n").is_err());
+    }
+
+    #[test]
+    fn test_partitions_tile_the_file() {
+        let records: Vec<String> = (0..37)
+            .map(|i| if i % 3 == 0 { format!("S;{}\n", i % 10) } else { format!("Station{};{}.{}\n", i % 5, i, i % 10) })
+            .collect();
+        for num_records in [1, 2, 3, 7, 20, 37] {
+            for trailing_newline in [true, false] {
+                let mut contents = records[..num_records].concat().into_bytes();
+                if !trailing_newline {
+                    contents.pop();
+                }
+                let path = fixture(&format!("tile-{}-{}", num_records, trailing_newline), &contents);
+                let mmap = MmappedFile::new(File::open(&path).unwrap()).unwrap();
+                for num_partitions in 1..=9 {
+                    let partitions = mmap.partition_into_slices(num_partitions, usize::MAX).unwrap();
+                    assert_eq!(partitions.len(), num_partitions);
+                    assert_eq!(partitions.concat(), contents, "{} records, {} partitions", num_records, num_partitions);
+                    // Every partition must start on a record boundary
+                    let mut start = 0;
+                    for partition in &partitions {
+                        assert!(start == 0 || start == contents.len() || contents[start - 1] == b'\n');
+                        start += partition.len();
+                    }
+                }
+                std::fs::remove_file(path).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_aligned_partitions_tile_the_file() {
+        let mut contents = Vec::new();
+        for i in 0..20_000 {
+            contents.extend(format!("Station{};{}.{}\n", i % 37, i % 100, i % 10).into_bytes());
+        }
+        let longest = contents.split(|&b| b == b'\n').map(|line| line.len() + 1).max().unwrap();
+        let path = fixture("tile-aligned", &contents);
+        let mmap = MmappedFile::new(File::open(&path).unwrap()).unwrap();
+        let data = mmap.as_slice();
+        let expected = aggregate(&partition_slice(data, 1), &Config::default()).unwrap().stations;
+        for align in [Align::None, Align::CacheLine, Align::Page] {
+            let align = align.bytes();
+            for num_partitions in 1..=9 {
+                let partitions = try_partition_slice_aligned(data, num_partitions, usize::MAX, align).unwrap();
+                assert_eq!(partitions.len(), num_partitions);
+                assert_eq!(partitions.concat(), contents, "align {}, {} partitions", align, num_partitions);
+                // Every partition starts on a record boundary, within one
+                // record past an aligned address
+                for partition in partitions.iter().skip(1).filter(|p| !p.is_empty()) {
+                    let start = partition.as_ptr() as usize - data.as_ptr() as usize;
+                    assert_eq!(data[start - 1], b'\n');
+                    assert!((partition.as_ptr() as usize) % align <= longest, "align {}, start {}", align, start);
+                }
+                assert_eq!(aggregate(&partitions, &Config::default()).unwrap().stations, expected);
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_stats_json() {
+        let data: &[u8] = b"A;1.0\nB;2.0\nA;3.0\n";
+        let aggregation = aggregate(&partition_slice(data, 2), &Config::default()).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&stats_json(&aggregation, Duration::from_millis(500))).unwrap();
+        assert_eq!(json["elapsed_secs"], 0.5);
+        assert_eq!(json["rows"], 3);
+        assert_eq!(json["rows_per_sec"], 6.0);
+        assert_eq!(json["bytes"], data.len());
+        assert_eq!(json["bytes_per_sec"], 36.0);
+        assert_eq!(json["threads"], 2);
+
+        // No division by zero
+        let json: serde_json::Value = serde_json::from_str(&stats_json(&aggregation, Duration::ZERO)).unwrap();
+        assert_eq!(json["rows_per_sec"], 0.0);
+    }
+
+    #[test]
+    fn test_max_stations() {
+        let config = Config { max_stations: 2, ..Config::default() };
+        assert!(thread_runner(b"A;1.0\nB;2.0\nA;3.0\n", &config).is_ok());
+        let err = thread_runner(b"A;1.0\nB;2.0\nC;3.0\n", &config).unwrap_err();
+        assert!(err.to_string().starts_with("Found 3 distinct stations, more than the limit of 2"), "{}", err);
+
+        // Each partition is within the limit, but not the merged result
+        let partitions: [&[u8]; 2] = [b"A;1.0\nB;2.0\n", b"C;3.0\nD;4.0\n"];
+        let err = aggregate(&partitions, &config).unwrap_err();
+        assert!(err.to_string().starts_with("Found 4 distinct stations, more than the limit of 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_check_merge() {
+        let partitions: [&[u8]; 2] = [b"A;1.0\nB;2.0\n", b"A;3.0\n"];
+        let mut aggregation = aggregate(&partitions, &Config::default()).unwrap();
+        assert!(aggregation.check_merge().is_ok());
+
+        // Lose the second partition's reading of A
+        aggregation.stations.get_mut(b"A".as_slice()).unwrap().count -= 1;
+        let err = aggregation.check_merge().unwrap_err();
+        assert_eq!(err.to_string(), "Merged stations hold 2 readings but 3 were processed");
+    }
+
+    #[test]
+    fn test_directory_input_matches_concatenation() {
+        let dir = std::env::temp_dir().join(format!("rs-1brc-{}-shards", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("part-0.txt"), "A;1.0\nB;2.0\n").unwrap();
+        std::fs::write(dir.join("part-1.txt"), "A;-3.0\nC;4.5\n").unwrap();
+        std::fs::write(dir.join("notes.md"), "not measurements").unwrap();
+
+        let config = Config { glob: "part-*.txt".parse().unwrap(), ..Config::default() };
+        let actual = aggregate_file(dir.to_str().unwrap(), &config).unwrap();
+        let data: &[u8] = b"A;1.0\nB;2.0\nA;-3.0\nC;4.5\n";
+        let expected = aggregate(&partition_slice(data, 4), &config).unwrap();
+        assert_eq!(actual.stations, expected.stations);
+        assert_eq!(actual.readings(), 4);
+
+        let config = Config { glob: "*.csv".parse().unwrap(), ..Config::default() };
+        assert!(aggregate_file(dir.to_str().unwrap(), &config).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_directory_parallelism_policy() {
+        let dir = std::env::temp_dir().join(format!("rs-1brc-{}-policy", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for shard in 0..7 {
+            let rows: String = (0..50).map(|i| format!("S{};{}.{}\n", (shard * i) % 11, i - 25, shard)).collect();
+            std::fs::write(dir.join(format!("part-{}.txt", shard)), rows).unwrap();
+        }
+
+        let dir = dir.to_str().unwrap();
+        let expected = aggregate_file(dir, &Config::default()).unwrap();
+        assert_eq!(expected.readings(), 7 * 50);
+        for (files, per_file) in [(1, None), (2, None), (3, Some(2)), (7, Some(1)), (16, Some(4))] {
+            let config = Config { files_in_parallel: files, threads_per_file: per_file, ..Config::default() };
+            let actual = aggregate_file(dir, &config).unwrap();
+            assert_eq!(actual.stations, expected.stations, "{} files at once, {:?} threads each", files, per_file);
+            assert_eq!(actual.readings(), expected.readings());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_station_runs_match_per_record_lookup() {
+        let data = b"A;1.0\nA;2.0\nB;3.0\nA;-4.0\nB;5.0\nB;6.0\nC;7.0\nA;8.0\nA;9.0\n";
+        let mut expected: HashMap<&[u8], WeatherStation> = HashMap::new();
+        for line in data.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+            let (name, value) = line.split_at(memchr::memchr(b';', line).unwrap());
+            expected.entry(name).or_default().add_measurement(parse_measurement(&value[1..]));
+        }
+
+        let (actual, readings) = thread_runner(data, &Config::default()).unwrap();
+        assert_eq!(readings, 9);
+        assert_eq!(actual.into_iter().collect::<HashMap<_, _>>(), expected);
+    }
+
+    #[test]
+    fn test_alternating_stations_never_share_a_run() {
+        let data: Vec<u8> = (0..100).flat_map(|i| if i % 2 == 0 { "A;1.0\n" } else { "B;-2.0\n" }.bytes()).collect();
+        let (stations, _) = thread_runner(&data, &Config::default()).unwrap();
+        let (a, b) = (stations[b"A".as_slice()], stations[b"B".as_slice()]);
+        assert_eq!((a.min, a.max, a.sum, a.count), (10, 10, 500, 50));
+        assert_eq!((b.min, b.max, b.sum, b.count), (-20, -20, -1000, 50));
+    }
+
+    #[test]
+    fn test_skipped_lines_are_counted() {
+        let data = b"A;1.0\nbad\nB;2.0\nC;x\n;3.0\nA;4.0\n";
+        let config = Config { on_error: OnError::Skip, ..Config::default() };
+        let aggregation = aggregate(&partition_slice(data, 3), &config).unwrap();
+        assert_eq!(aggregation.readings(), 3);
+        assert_eq!(aggregation.skipped, Skipped { count: 3, offsets: vec![6, 16, 20] });
+        assert_eq!(aggregation.skipped.to_string(), "Skipped 3 malformed lines, e.g. at byte 6, 16, 20");
+
+        let many: Vec<u8> = b"bad\n".repeat(8);
+        let skipped = aggregate(&partition_slice(&many, 2), &config).unwrap().skipped;
+        assert_eq!(skipped, Skipped { count: 8, offsets: vec![0, 4, 8, 12, 16] });
+        assert_eq!(Skipped::default().to_string(), "Skipped 0 malformed lines");
+    }
+
+    #[test]
+    fn test_longest_name() {
+        let partitions = ["Hamburg;12.0\nLlanfairpwllgwyngyll;1.0\n".as_bytes(), "São Paulo;20.0\n".as_bytes(), b"Oslo;-3.0\n"];
+        let aggregation = aggregate(&partitions, &Config::default()).unwrap();
+        let longest: Vec<_> = aggregation.threads.iter().map(|t| t.longest_name).collect();
+        assert_eq!(longest, [20, 10, 4]);
+        assert_eq!(aggregation.longest_name(), 20);
+        assert_eq!(aggregate(&[], &Config::default()).unwrap().longest_name(), 0);
+    }
+
+    #[test]
+    fn test_resume_matches_single_run() {
+        let lines: Vec<String> = (0..200).map(|i| format!("Station{};{}.{}\n", i % 7, i % 50 - 20, i % 10)).collect();
+        let whole = fixture("resume-whole", lines.concat().as_bytes());
+        let first = fixture("resume-first", lines[..100].concat().as_bytes());
+        let second = fixture("resume-second", lines[100..].concat().as_bytes());
+        let (saved, resumed, expected) = (fixture("resume-state", b""), fixture("resume-out", b""), fixture("resume-expected", b""));
+        let run_with = |input: &std::path::Path, config: Config| {
+            let config = Config { input: input.to_str().unwrap().to_string(), ..config };
+            run(&Cli { command: None, config }).unwrap();
+        };
+
+        run_with(&first, Config { output: Some(resumed.clone()), merge_output: Some(saved.clone()), ..Config::default() });
+        run_with(&second, Config { output: Some(resumed.clone()), resume: Some(saved.clone()), ..Config::default() });
+        run_with(&whole, Config { output: Some(expected.clone()), ..Config::default() });
+        assert_eq!(std::fs::read(&resumed).unwrap(), std::fs::read(&expected).unwrap());
+        for path in [whole, first, second, saved, resumed, expected] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_memory_limit_streams_large_files() {
+        let contents: String = (0..5000).map(|i| format!("Station{};{}.{}\n", i % 13, i % 90 - 45, i % 10)).collect();
+        let path = fixture("memory-limit", contents.as_bytes());
+        let file = File::open(&path).unwrap();
+        let path = path.to_str().unwrap();
+        let config = Config { threads: 2, ..Config::default() };
+        let mapped = aggregate_file(path, &config).unwrap();
+
+        let limited = Config { memory_limit: Some(contents.len() / 2), ..config.clone() };
+        assert!(over_memory_limit(&file, &limited).unwrap());
+        let streamed = aggregate_file(path, &limited).unwrap();
+        assert_eq!(streamed.stations, mapped.stations);
+        assert_eq!(streamed.readings(), 5000);
+
+        let roomy = Config { memory_limit: Some(contents.len()), ..config.clone() };
+        assert!(!over_memory_limit(&file, &roomy).unwrap());
+
+        let tiny = Config { memory_limit: Some(1000), ..config };
+        assert!(aggregate_file(path, &tiny).unwrap_err().to_string().starts_with("Memory limit of 1000 bytes is too small"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_no_output() {
+        let contents: String = (0..300).map(|i| format!("S{};{}.0\n", i % 9, i % 40)).collect();
+        let (input, output) = (fixture("no-output-in", contents.as_bytes()), fixture("no-output-out", b""));
+        let config = Config {
+            input: input.to_str().unwrap().to_string(),
+            output: Some(output.clone()),
+            no_output: true,
+            expect_rows: Some(300),
+            ..Config::default()
+        };
+        run(&Cli { command: None, config: config.clone() }).unwrap();
+        assert!(std::fs::read(&output).unwrap().is_empty());
+
+        let config = Config { expect_rows: Some(301), ..config };
+        assert!(run(&Cli { command: None, config }).is_err());
+        for path in [input, output] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_stations_file() {
+        let input = fixture("stations-file-in", b"Oslo;1.0\nAbha;2.0\nOslo;3.0\n");
+        let list = fixture("stations-file-list", b"Oslo\r\n\nCairo\n");
+        let output = fixture("stations-file-out", b"");
+        let config = Config {
+            input: input.to_str().unwrap().to_string(),
+            output: Some(output.clone()),
+            stations_file: Some(list.clone()),
+            ..Config::default()
+        };
+        run(&Cli { command: None, config }).unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(),
+                   "Oslo: min=1 max=3 mean=2.0 count=2\nCairo: min=- max=- mean=- count=0\n");
+        for path in [input, list, output] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_count_newlines() {
+        let contents: String = (0..3000).map(|i| format!("Station{};{}.{}\n", i % 17, i % 80, i % 10)).collect();
+        let path = fixture("count-newlines", contents.as_bytes());
+        let mmap = MmappedFile::new(File::open(&path).unwrap()).unwrap();
+        let data = mmap.as_slice();
+        for range in [0..0, 0..1, 0..data.len(), 5..data.len() - 3, 100..133] {
+            let naive = data[range.clone()].iter().filter(|&&b| b == b'\n').count();
+            assert_eq!(count_newlines(&data[range.clone()]), naive, "{:?}", range);
+        }
+
+        let expected = aggregate(&partition_slice(data, 3), &Config::default()).unwrap();
+        let config = Config { presize: true, ..Config::default() };
+        assert_eq!(aggregate(&partition_slice(data, 3), &config).unwrap().stations, expected.stations);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_two_pass_matches_single_pass() {
+        let data: Vec<u8> = (0..3000).flat_map(|i| format!("Station{};{}.{}\n", i * 7 % 251, i % 80 - 40, i % 10).into_bytes()).collect();
+        for num_partitions in [1, 3, 8] {
+            let expected = aggregate(&partition_slice(&data, num_partitions), &Config::default()).unwrap();
+            let config = Config { two_pass: true, ..Config::default() };
+            let actual = aggregate(&partition_slice(&data, num_partitions), &config).unwrap();
+            assert_eq!(actual.stations, expected.stations, "{} partitions", num_partitions);
+            assert_eq!(actual.readings(), 3000);
+        }
+
+        // The first pass splits lines as the configured layout does
+        let count = |data: &[u8], config: &Config| match (config.value_first, config.last_delimiter) {
+            (true, _) => distinct_stations::<true, false, false>(data, config),
+            (false, true) => distinct_stations::<false, true, false>(data, config),
+            (false, false) => distinct_stations::<false, false, false>(data, config),
+        };
+        assert_eq!(count(&data, &Config::default()), 251);
+        assert_eq!(count(b"A;B;1.0\nA;C;2.0\nA;B;3.0", &Config::default()), 1);
+        assert_eq!(count(b"A;B;1.0\nA;C;2.0\nA;B;3.0", &Config { last_delimiter: true, ..Config::default() }), 2);
+        assert_eq!(count(b"1.0;A\n2.0;B\n\n3.0;A\n", &Config { value_first: true, ..Config::default() }), 2);
+        assert_eq!(distinct_stations::<true, false, false>(b"1.0;A\0\n2.0;A\n", &Config::default()), 2);
+        assert_eq!(distinct_stations::<true, false, true>(b"1.0;A\0\n2.0;A\n", &Config::default()), 1);
+    }
+
+    #[test]
+    fn test_assert_sorted_input() {
+        let sorted = b"A;1.0\nA;-2.0\nB;3.0\nC;4.0\nC;5.5\nC;-6.0\n";
+        let config = Config { assert_sorted_input: true, ..Config::default() };
+        for num_partitions in 1..=4 {
+            let expected = aggregate(&partition_slice(sorted, num_partitions), &Config::default()).unwrap();
+            let actual = aggregate(&partition_slice(sorted, num_partitions), &config).unwrap();
+            assert_eq!(actual.stations, expected.stations, "{} partitions", num_partitions);
+            assert_eq!(actual.readings(), 6);
+        }
+
+        let err = thread_runner(b"A;1.0\nB;2.0\nA;3.0\n", &config).unwrap_err();
+        assert_eq!(err.to_string(), "Input is not grouped by station: \"A\" appears again at byte 12");
+        let config = Config { max_stations: 2, ..config };
+        assert!(thread_runner(b"A;1.0\nB;2.0\nC;3.0\n", &config).is_err());
+    }
+
+    #[test]
+    fn test_cpu_time_per_thread() {
+        let data: String = (0..2000).map(|i| format!("S{};{}.0\n", i % 5, i % 30)).collect();
+        let data = data.as_bytes();
+        for threads in [1, 3, 4] {
+            let config = Config { threads, ..Config::default() };
+            let static_split = aggregate(&partition_slice(data, threads), &config).unwrap();
+            let queued = aggregate(&partition_slice(data, 16), &config).unwrap();
+            if cfg!(unix) {
+                assert_eq!(static_split.cpu_times.len(), threads);
+                assert_eq!(queued.cpu_times.len(), threads);
+            }
+        }
+
+        let secs = |ms| Duration::from_millis(ms);
+        assert_eq!(cpu_summary(&[secs(300), secs(100), secs(200)]),
+                   "CPU time across 3 threads: min 0.100s, median 0.200s, max 0.300s, imbalance 1.50x");
+        assert_eq!(cpu_summary(&[]), "CPU time per thread: not available");
+    }
+
+    #[test]
+    fn test_bench_parse_only() {
+        let config = Config { bench_parse_only: true, ..Config::default() };
+        let (stations, readings) = thread_runner(b"A;1.0\nB;2.0\nA;3.0\n", &config).unwrap();
+        assert!(stations.is_empty());
+        assert_eq!(readings, 3);
+        assert!(thread_runner(b"A;1.0\nbad\n", &config).is_err());
+    }
+
+    #[test]
+    fn test_count_only_matches_full_counts() {
+        let contents: String = (0..500).map(|i| format!("S{};{}.{}\n", i % 7, i % 60 - 30, i % 10)).collect();
+        let path = fixture("count-only", contents.as_bytes());
+        let path = path.to_str().unwrap();
+        let full = aggregate_file(path, &Config::default()).unwrap();
+        let config = Config { count_only: Some(CountOrder::Name), ..Config::default() };
+        let counted = aggregate_file(path, &config).unwrap();
+        assert_eq!(counted.readings(), full.readings());
+        assert_eq!(counted.stations.len(), full.stations.len());
+        for (name, station) in &full.stations {
+            assert_eq!(counted.stations[name].count(), station.count(), "{}", String::from_utf8_lossy(name));
+        }
+        std::fs::remove_file(path).unwrap();
+
+        // Only lines without a name are malformed
+        assert_eq!(thread_runner(b"A;oops\nA;\n", &config).unwrap().1, 2);
+        assert!(thread_runner(b"A;1.0\nno delimiter\n", &config).is_err());
+        assert!(thread_runner(b";1.0\n", &config).is_err());
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let contents: String = (0..1000).map(|i| format!("S{};{}.0\n", i % 3, i % 50)).collect();
+        let path = fixture("sample-rate", contents.as_bytes());
+        let path = path.to_str().unwrap();
+        let full = aggregate_file(path, &Config::default()).unwrap();
+        let every = aggregate_file(path, &Config { sample_rate: 1, ..Config::default() }).unwrap();
+        assert_eq!(every.stations, full.stations);
+
+        // Half the records are read, and the counts scaled back up
+        let half = aggregate_file(path, &Config { sample_rate: 2, ..Config::default() }).unwrap();
+        assert!((490..=510).contains(&half.readings()), "{}", half.readings());
+        for (name, station) in &full.stations {
+            let sampled = half.stations[name];
+            assert!(sampled.count().abs_diff(station.count()) <= 8, "{:?}", name);
+            assert!((sampled.mean() - station.mean()).abs() < 5.0);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_record_length_cap() {
+        let mut data = b"A;1.0\n".to_vec();
+        data.extend(std::iter::repeat_n(b'x', 1000));
+        data.extend(b"\nB;2.0\n");
+        let err = try_partition_slice(&data, 4, 100).unwrap_err();
+        assert_eq!(err.to_string(), "Record too long: no newline within 100 bytes of byte 253");
+        assert_eq!(try_partition_slice(&data, 4, 1000).unwrap(), partition_slice(&data, 4));
+
+        // Running off the end of the file isn't an error
+        let giant = vec![b'x'; 1000];
+        assert_eq!(try_partition_slice(&giant, 1, 10).unwrap(), [&giant[..]]);
+        assert!(try_partition_slice(&giant, 2, 10).is_err());
+        assert_eq!(try_partition_slice(&giant, 2, 500).unwrap(), [&giant[..], &[][..]]);
+    }
+
+    #[test]
+    fn test_mapped_partition_errors() {
+        let mut contents = b"A;1.0\n".repeat(10);
+        contents.extend(std::iter::repeat_n(b'x', 200));
+        contents.extend(b"\nB;2.0\n");
+        let path = fixture("partition-errors", &contents);
+        let mmap = MmappedFile::new(File::open(&path).unwrap()).unwrap();
+        let partitions = mmap.partition_into_slices(3, 1000).unwrap();
+        assert_eq!((partitions.len(), partitions.concat()), (3, contents.clone()));
+
+        let err = mmap.partition_into_slices(3, 50).unwrap_err();
+        assert_eq!(err.to_string(), "Record too long: no newline within 50 bytes of byte 89");
+        let err = mmap.partition_into_slices(0, 1000).unwrap_err();
+        assert_eq!(err.to_string(), "Can't split the input into 0 partitions");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_print_partitions() {
+        let data = b"A;1.0\nB;2.0\nC;3.0\nD;4.0\nE;5.0\n";
+        let partitions = partition_slice(data, 4);
+        let mut printed = Vec::new();
+        print_partitions(&mut printed, &partitions).unwrap();
+        let printed = String::from_utf8(printed).unwrap();
+        assert_eq!(printed.lines().next().unwrap(), r#"Partition 0: bytes 0..12 (12 bytes), first "A;1.0", last "B;2.0""#);
+        assert!(printed.contains(r#"Partition 3: bytes 30..30 (0 bytes)"#), "{}", printed);
+
+        // The printed ranges tile the file
+        let mut expected_start = 0;
+        for line in printed.lines() {
+            let range = line.split(' ').nth(3).unwrap();
+            let (start, end) = range.split_once("..").unwrap();
+            assert_eq!(start.parse::<usize>().unwrap(), expected_start);
+            expected_start = end.parse().unwrap();
+        }
+        assert_eq!(expected_start, data.len());
+    }
+
+    #[test]
+    fn test_mmap_rejects_directories() {
+        let dir = std::env::temp_dir();
+        let err = MmappedFile::new(File::open(&dir).unwrap()).err().unwrap();
+        assert_eq!(err.to_string(), "Expected a file, got a directory");
+    }
+
+    #[test]
+    fn test_rare_stations() {
+        let data = b"Hamburg;1.0\nHamburg;2.0\nHamburgg;3.0\nOslo;4.0\nHamburg;5.0\nOslo;6.0\n";
+        let stations = aggregate(&partition_slice(data, 2), &Config::default()).unwrap().stations;
+        assert_eq!(rare_stations(&stations, 2), [(&b"Hamburgg"[..], 1)]);
+        assert_eq!(rare_stations(&stations, 3), [(&b"Hamburgg"[..], 1), (&b"Oslo"[..], 2)]);
+        assert!(rare_stations(&stations, 1).is_empty());
+    }
+
+    #[test]
+    fn test_wide_stations() {
+        let contents = b"Oslo;-5.0\nMerged;-40.0\nOslo;20.0\nMerged;85.5\nCairo;35.0\nMerged;10.0\n";
+        let path = fixture("max-span", contents);
+        let stations = aggregate_file(path.to_str().unwrap(), &Config::default()).unwrap().stations;
+        assert_eq!(wide_stations(&stations, 1200), [(&b"Merged"[..], 1255)]);
+        assert_eq!(wide_stations(&stations, 250), [(&b"Merged"[..], 1255)]);
+        assert_eq!(wide_stations(&stations, 249), [(&b"Merged"[..], 1255), (&b"Oslo"[..], 250)]);
+        assert!(wide_stations(&stations, 1255).is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_measurement_width_gives_same_results() {
+        // The same fixture and expected output are checked with and without
+        // the wide-measurements feature
+        let contents = b"Hamburg;12.0\nOslo;-999.9\nHamburg;-0.1\nOslo;999.9\nCairo;45.6\nHamburg;34.2\n";
+        let path = fixture("measurement-width", contents);
+        let stations = aggregate_file(path.to_str().unwrap(), &Config::default()).unwrap().stations;
+        let mut out = Vec::new();
+        write_results(&mut out, &stations, &Config { format: Format::Raw, ..Config::default() }).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Cairo 456 456 1 456\nHamburg -1 461 3 342\nOslo -9999 0 2 9999\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_more_partitions_than_threads() {
+        let data: String = (0..2000).map(|i| format!("Station{};{}.{}\n", i % 13, i % 90 - 45, i % 10)).collect();
+        let expected = aggregate(&partition_slice(data.as_bytes(), 2), &Config::default()).unwrap();
+        for threads in [1, 2, 3] {
+            let config = Config { threads, ..Config::default() };
+            let actual = aggregate(&partition_slice(data.as_bytes(), 64), &config).unwrap();
+            assert_eq!(actual.stations, expected.stations);
+            assert_eq!(actual.threads.len(), threads);
+            assert_eq!(actual.readings(), 2000);
+            assert_eq!(actual.threads.iter().map(|t| t.bytes).sum::<usize>(), data.len());
+        }
+
+        let config = Config { threads: 2, ..Config::default() };
+        let err = aggregate(&partition_slice(b"A;1.0\nB;2.0\nbad\nC;3.0\n", 8), &config).unwrap_err();
+        assert_eq!(err.to_string(), "Malformed record at byte 12: \"bad\"");
+    }
+
+    #[test]
+    fn test_chunked_matches_static_partitions() {
+        let data: Vec<u8> = (0..300_000).flat_map(|i| format!("Station{};{}.{}\n", i % 41, i % 90 - 45, i % 10).into_bytes()).collect();
+        assert!(data.len() > 4 << 20);
+        let expected = aggregate_slice(&data, &Config::default(), &RunContext::default()).unwrap();
+        let config = Config { threads: 2, chunk_size: Some(1), ..Config::default() };
+        let chunked = aggregate_slice(&data, &config, &RunContext::default()).unwrap();
+        assert_eq!(chunked.stations, expected.stations);
+        assert_eq!(chunked.threads.len(), 2);
+    }
+
+    #[test]
+    fn test_checksum_is_independent_of_thread_count() {
+        let checksum = |bytes: &[u8]| hex(&Sha256::digest(bytes));
+        let data: String = (0..1000).map(|i| format!("Station{};{}.{}\n", i % 17, i % 90 - 45, i % 10)).collect();
+        let checksum_with = |threads| {
+            let config = Config { threads, ..Config::default() };
+            let stations = aggregate_slice(data.as_bytes(), &config, &RunContext::default()).unwrap().stations;
+            let mut out = Vec::new();
+            write_results(&mut out, &stations, &config).unwrap();
+            checksum(&out)
+        };
+        assert_eq!(checksum_with(1), checksum_with(4));
+        assert_eq!(checksum(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn test_checksum_passes_output_through() {
+        let data: String = (0..100_000).map(|i| format!("Station{};{}.{}\n", i % 30_011, i % 90 - 45, i % 10)).collect();
+        let config = Config { max_stations: 30_011, ..Config::default() };
+        let stations = aggregate_slice(data.as_bytes(), &config, &RunContext::default()).unwrap().stations;
+        for format in [Format::Verbose, Format::Official, Format::Table, Format::Csv, Format::Raw] {
+            let mut expected = Vec::new();
+            write_results(&mut expected, &stations, &Config { format, ..config.clone() }).unwrap();
+            let mut out = Vec::new();
+            emit_results(&mut out, &stations, None, &Config { format, checksum: true, ..config.clone() }).unwrap();
+            assert_eq!(out, expected, "{:?}", format);
+
+            let mut hashing = HashingWriter { inner: Vec::new(), hasher: Sha256::new() };
+            for piece in expected.chunks(expected.len() / 3 + 1) {
+                hashing.write_all(piece).unwrap();
+            }
+            assert_eq!(hex(&hashing.hasher.finalize()), hex(&Sha256::digest(&expected)));
+        }
+    }
+
+    #[test]
+    fn test_memory_summary() {
+        let data: String = (0..500).map(|i| format!("Station{};1.0\n", i % 37)).collect();
+        let aggregation = aggregate_slice(data.as_bytes(), &Config::default(), &RunContext::default()).unwrap();
+        assert_eq!(aggregation.stations.len(), 37);
+        assert!(memory_summary(&aggregation).ends_with("KiB merged (37 stations)"));
+
+        // Each thread saw every station, and the merged map owns its names
+        let entry = std::mem::size_of::<(&[u8], WeatherStation)>() + 1;
+        assert!(aggregation.partial_map_bytes >= 4 * 37 * entry);
+        let names: usize = aggregation.stations.keys().map(Vec::len).sum();
+        assert!(map_bytes(&aggregation.stations) >= 37 * entry + names);
+    }
+
+    #[test]
+    fn test_partition_empty_file() {
+        let path = fixture("empty", b"");
+        let mmap = MmappedFile::new(File::open(&path).unwrap()).unwrap();
+        assert!(mmap.partition_into_slices(4, usize::MAX).unwrap().iter().all(|p| p.is_empty()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_expect_rows() {
+        let partitions: [&[u8]; 2] = [b"A;1.0\nB;2.0\n", b"A;3.0\n"];
+        let aggregation = aggregate(&partitions, &Config::default()).unwrap();
+        assert_eq!(aggregation.readings(), 3);
+        assert!(check_expected_rows(aggregation.readings(), None).is_ok());
+        assert!(check_expected_rows(aggregation.readings(), Some(3)).is_ok());
+        let err = check_expected_rows(aggregation.readings(), Some(4)).unwrap_err();
+        assert_eq!(err.to_string(), "Expected 4 readings but processed 3");
+    }
+
+    /// The original raw-pointer partitioning, kept to check the slice-based
+    /// version splits at exactly the same bytes.
+    fn reference_partitions(data: &[u8], num_partitions: usize) -> Vec<std::ops::Range<usize>> {
+        let partition_size = data.len() / num_partitions;
+        let mut ranges = Vec::new();
+        let mut start: usize = 0;
+        for _ in 0..num_partitions {
+            let mut end: usize = (start + partition_size).min(data.len());
+            let mut stop = false;
+            while end < data.len() && !stop {
+                if data[end] == b'\n' {
+                    stop = true;
+                }
+                end += 1;
+            }
+            ranges.push(start..end);
+            start = end;
+        }
+        ranges
+    }
+
+    #[test]
+    fn test_partition_boundaries_match_reference() {
+        let mut rng = Rng::new(42);
+        for case in 0..20 {
+            let contents: Vec<u8> = (0..rng.below(300))
+                .map(|_| if rng.below(6) == 0 { b'\n' } else { b'x' })
+                .collect();
+            let path = fixture(&format!("reference-{}", case), &contents);
+            let mmap = MmappedFile::new(File::open(&path).unwrap()).unwrap();
+            assert_eq!(mmap.as_slice(), &contents[..]);
+            for num_partitions in 1..=8 {
+                let ranges: Vec<_> = mmap
+                    .partition_into_slices(num_partitions, usize::MAX)
+                    .unwrap()
+                    .iter()
+                    .map(|p| {
+                        let start = p.as_ptr() as usize - mmap.as_slice().as_ptr() as usize;
+                        start..start + p.len()
+                    })
+                    .collect();
+                assert_eq!(ranges, reference_partitions(&contents, num_partitions));
+            }
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_deterministic_iteration_order() {
+        let data: Vec<u8> = (0..200).flat_map(|i| format!("Station{};1.0\n", i).into_bytes()).collect();
+        let config = Config { deterministic: true, ..Config::default() };
+        let order = || thread_runner(&data, &config).unwrap().0.into_keys().collect::<Vec<_>>();
+        let first = order();
+        for _ in 0..5 {
+            assert_eq!(order(), first);
+        }
+    }
+
+    #[test]
+    fn test_zstd_input_matches_plaintext() {
+        let contents = b"Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nHamburg;-4.2\n";
+        let plain = fixture("plain.txt", contents);
+        let compressed = fixture("compressed.txt.zst", &zstd::encode_all(&contents[..], 0).unwrap());
+
+        let config = Config { threads: 2, ..Config::default() };
+        let expected = aggregate_file(plain.to_str().unwrap(), &config).unwrap();
+        let actual = aggregate_file(compressed.to_str().unwrap(), &config).unwrap();
+        assert_eq!(actual.readings(), 4);
+        assert_eq!(actual.stations, expected.stations);
+        std::fs::remove_file(plain).unwrap();
+        std::fs::remove_file(compressed).unwrap();
+    }
+
+    #[test]
+    fn test_partitions_keep_multibyte_names_whole() {
+        let names = ["München", "São Paulo", "Zürich", "İzmir", "Ho Chi Minh City", "Φ"];
+        let contents: String = (0..60).map(|i| format!("{};{}.{}\n", names[i % names.len()], i, i % 10)).collect();
+        for num_partitions in 1..=16 {
+            let partitions = partition_slice(contents.as_bytes(), num_partitions);
+            for partition in &partitions {
+                assert!(std::str::from_utf8(partition).is_ok());
+            }
+            let aggregation = aggregate(&partitions, &Config::default()).unwrap();
+            assert_eq!(aggregation.stations.len(), names.len());
+            for name in aggregation.stations.keys() {
+                assert!(names.contains(&std::str::from_utf8(name).unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_is_independent_of_partial_order() {
+        let chunks: [&[u8]; 3] = [
+            b"Berlin;1.0\nHamburg;-2.0\n",
+            b"Berlin;9.9\nMunich;3.0\n",
+            b"Hamburg;4.5\nBerlin;-7.1\nOslo;0.0\n",
+        ];
+        let config = Config { deterministic: true, ..Config::default() };
+        let partials = |order: [usize; 3]| order.map(|i| thread_runner(chunks[i], &config).unwrap().0).to_vec();
+
+        let merged = merge_partials(partials([0, 1, 2]));
+        assert_eq!(merge_partials(partials([2, 0, 1])), merged);
+        assert_eq!(merge_partials(partials([1, 2, 0])), merged);
+
+        // Berlin is combined from three partials, Munich moved in from one
+        let berlin = &merged[&b"Berlin"[..]];
+        assert_eq!((berlin.min_raw(), berlin.max_raw(), berlin.sum_raw(), berlin.count()), (-71, 99, 38, 3));
+        let munich = &merged[&b"Munich"[..]];
+        assert_eq!((munich.min_raw(), munich.max_raw(), munich.sum_raw(), munich.count()), (30, 30, 30, 1));
+    }
+
+    #[test]
+    fn test_first_n_bytes_snaps_to_record_boundary() {
+        let data: &[u8] = b"A;1.0\nB;-2.0\nA;3.5\nC;0.0\n";
+        assert_eq!(record_prefix(data, 5), b"");
+        assert_eq!(record_prefix(data, 6), b"A;1.0\n");
+        assert_eq!(record_prefix(data, 18), b"A;1.0\nB;-2.0\n");
+        assert_eq!(record_prefix(data, 19), b"A;1.0\nB;-2.0\nA;3.5\n");
+        assert_eq!(record_prefix(data, 1000), data);
+
+        let path = fixture("first-n-bytes", data);
+        for limit in 1..=data.len() + 1 {
+            let prefix = record_prefix(data, limit);
+            let expected = aggregate(&partition_slice(prefix, 4), &Config::default()).unwrap();
+            let readings = prefix.iter().filter(|&&b| b == b'\n').count();
+            for backend in [Backend::Mmap, Backend::Stream] {
+                let config = Config { first_n_bytes: Some(limit), backend, ..Config::default() };
+                let actual = aggregate_file(path.to_str().unwrap(), &config).unwrap();
+                assert_eq!(actual.stations, expected.stations, "{} bytes, {:?}", limit, backend);
+                assert_eq!(actual.readings(), readings);
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_append_output_keeps_earlier_runs() {
+        let input = fixture("append-in", b"Oslo;-3.0\nAbha;12.5\nOslo;4.5\n");
+        let report = std::env::temp_dir().join(format!("rs-1brc-append-out-{}", std::process::id()));
+        let _ = std::fs::remove_file(&report);
+        let config = Config { input: input.to_str().unwrap().to_string(), append_output: Some(report.clone()),
+                              format: Format::Csv, ..Config::default() };
+        for _ in 0..2 {
+            run(&Cli { command: None, config: config.clone() }).unwrap();
+        }
+
+        let contents = std::fs::read_to_string(&report).unwrap();
+        let blocks: Vec<_> = contents.split("# ").skip(1).collect();
+        assert_eq!(blocks.len(), 2, "{}", contents);
+        for block in blocks {
+            let (header, results) = block.split_once('\n').unwrap();
+            let [timestamp, name, rows] = header.split(' ').collect::<Vec<_>>().try_into().unwrap();
+            assert!(timestamp.ends_with('Z') && timestamp.len() == 20, "{}", timestamp);
+            assert_eq!((name, rows), (config.input.as_str(), "rows=3"));
+            assert_eq!(results, "station,min,mean,max,count\nAbha,12.5,12.5,12.5,1\nOslo,-3.0,0.8,4.5,2\n");
+        }
+
+        let verify = Cli { command: Some(Command::Verify { input: config.input.clone(), expected: report.clone() }), config };
+        assert_eq!(run(&verify).unwrap_err().to_string(), "--append-output only applies when writing results");
+        for path in [input, report] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_utc_timestamp() {
+        let at = |secs| utc_timestamp(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_709_251_199), "2024-02-29T23:59:59Z");
+        assert_eq!(at(4_102_444_800), "2100-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_copy_on_open() {
+        let path = fixture("copy-on-open", b"Oslo;1.0\nAbha;2.0\nOslo;3.0\n");
+        let input = path.to_str().unwrap();
+        let config = Config { copy_on_open: true, ..Config::default() };
+        let expected = aggregate_file(input, &Config::default()).unwrap().stations;
+        assert_eq!(aggregate_file(input, &config).unwrap().stations, expected);
+
+        // The copy outlives the file being truncated, where touching a mapping
+        // of it would raise SIGBUS
+        let contents = Contents::load(File::open(&path).unwrap(), &config).unwrap();
+        assert!(matches!(contents, Contents::Copied(_)));
+        File::options().write(true).open(&path).unwrap().set_len(0).unwrap();
+        let stations = aggregate_slice(contents.as_slice(), &config, &RunContext::default()).unwrap().stations;
+        assert_eq!(stations, expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_report_overlaps() {
+        let first = fixture("overlaps-first", b"Hamburg;12.0\nOslo;-3.0\nHamburg;34.2\n");
+        let second = fixture("overlaps-second", b"Hamburg;20.5\nAbha;30.0\n");
+        let third = fixture("overlaps-third", b"Oslo;5.0\nAbha;31.5\n");
+        let inputs = [&first, &second, &third].map(|path| path.to_str().unwrap().to_string());
+        let mut sightings = Sightings::new();
+        let mut merged = StationMap::with_hasher(StationHasher::Fixed);
+        for input in &inputs {
+            let stations = aggregate_file(input, &Config::default()).unwrap().stations;
+            record_sightings(&mut sightings, input, &stations);
+            merge_stations(&mut merged, stations);
+        }
+        let mut out = Vec::new();
+        write_overlaps(&mut out, &sightings, &Config::default()).unwrap();
+        let [first, second, third] = &inputs;
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   format!("Station \"Abha\" in 2 inputs: {1} 30 to 30 (1), {2} 31.5 to 31.5 (1); ranges are disjoint\n\
+                            Station \"Hamburg\" in 2 inputs: {0} 12 to 34.2 (2), {1} 20.5 to 20.5 (1); ranges overlap\n\
+                            Station \"Oslo\" in 2 inputs: {0} -3 to -3 (1), {2} 5 to 5 (1); ranges are disjoint\n", first, second, third));
+
+        // Reporting leaves the merge itself alone
+        assert_eq!(merged[&b"Hamburg"[..]].count(), 3);
+        assert_eq!(merged.len(), 3);
+        for input in inputs {
+            std::fs::remove_file(input).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        // Enough records for several slices between deadline checks
+        let mut data = Vec::new();
+        for i in 0..300_000 {
+            writeln!(data, "Station{};{}.{}", i % 97, i % 50, i % 10).unwrap();
+        }
+        let path = fixture("timeout", &data);
+        let input = path.to_str().unwrap();
+        let expected = aggregate_file(input, &Config::default()).unwrap();
+        assert_eq!(expected.readings(), 300_000);
+
+        for backend in [Backend::Mmap, Backend::Stream] {
+            // A generous timeout finishes as usual
+            let config = Config { backend, threads: 2, ..Config::default() };
+            let context = RunContext { deadline: Some(Deadline::after(Duration::from_secs(3600))), ..RunContext::default() };
+            let aggregation = aggregate_file_with(input, &config, &context).unwrap();
+            assert_eq!(aggregation.stations, expected.stations, "{:?}", backend);
+            assert!(!context.deadline.unwrap().fired());
+
+            // One that has already passed stops before the first slice, and
+            // the partial results still merge consistently
+            let context = RunContext { deadline: Some(Deadline::after(Duration::ZERO)), ..RunContext::default() };
+            let aggregation = aggregate_file_with(input, &config, &context).unwrap();
+            assert!(aggregation.readings() < 300_000, "{:?}", backend);
+            assert_eq!(aggregation.stations.values().map(|s| s.count as usize).sum::<usize>(), aggregation.readings());
+            assert!(context.deadline.unwrap().fired());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_explain_reflects_flags() {
+        let input = fixture("explain", b"Oslo;1.0\nAbha;2.0\n");
+        let path = input.to_str().unwrap();
+        let plan = |config: &Config| {
+            let mut out = Vec::new();
+            explain(&mut out, path, config).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let default = plan(&Config { threads: 4, ..Config::default() });
+        assert_eq!(default, format!("\
+Plan for {}:
+  input: file of 18 bytes
+  backend: mmap
+  threads: 4, partitions: 4, one per thread
+  hasher: SipHash with random keys
+  records: name;value, 1 decimal, malformed lines fail the run
+  output: verbose, 1 decimal place, to stdout
+", path));
+
+        let config = Config { partitions: Some(7), deterministic: true, format: Format::Csv, on_error: OnError::Skip,
+                              last_delimiter: true, decimals: 2, output: Some("out.csv".into()), ..Config::default() };
+        let lines: Vec<_> = plan(&config).lines().map(String::from).collect();
+        assert_eq!(lines[3], format!("  threads: {}, partitions: 7 (--partitions)", config.threads));
+        assert_eq!(lines[4], "  hasher: SipHash with fixed keys (--deterministic)");
+        assert_eq!(lines[5], "  records: name;value, split at the last ;, 2 decimals, malformed lines are skipped");
+        assert_eq!(lines[6], "  output: csv, 2 decimal places, to out.csv");
+
+        // Streaming, whether asked for or chosen for a large file, isn't partitioned
+        let streamed = plan(&Config { backend: Backend::Stream, ..Config::default() });
+        assert!(streamed.contains("  backend: stream (--backend stream)"), "{}", streamed);
+        let limited = plan(&Config { memory_limit: Some(10), ..Config::default() });
+        assert!(limited.contains("  backend: stream (the file is larger than --memory-limit of 10 bytes)"), "{}", limited);
+        assert!(!limited.contains("partitions"));
+        std::fs::remove_file(input).unwrap();
+    }
+
+    #[test]
+    fn test_normalize_space() {
+        for (name, expected) in [(&b"New York"[..], &b"New York"[..]), (b"New  York", b"New York"), (b"  New York ", b"New York"),
+                                 (b"New \t York", b"New \t York"), (b"   ", b""), (b"", b""), (b"A    B  C", b"A B C")] {
+            let mut name = name.to_vec();
+            normalize_space(&mut name);
+            assert_eq!(name, expected);
+        }
+    }
+
+    #[test]
+    fn test_normalize_space_merges_stations() {
+        let input = fixture("normalize-space", b"New York;1.0\nNew  York;-3.0\n New York;5.0\nOslo;2.0\n");
+        let output = fixture("normalize-space-out", b"");
+        let path = input.to_str().unwrap();
+        assert_eq!(aggregate_file(path, &Config::default()).unwrap().stations.len(), 4);
+
+        let config = Config { input: path.to_string(), output: Some(output.clone()), normalize_space: true,
+                              metric: Some(Metric::BelowZero), ..Config::default() };
+        let stations = aggregate_file(path, &config).unwrap().stations;
+        assert_eq!(stations.len(), 2);
+        assert_eq!(stations[&b"New York"[..]], WeatherStation { min: -30, max: 50, sum: 30, count: 3 });
+
+        // The metric column merges the same way
+        run(&Cli { command: None, config }).unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "\
+New York: min=-3 max=5 mean=1.0 below_zero=1 count=3
+Oslo: min=2 max=2 mean=2.0 below_zero=0 count=1
+");
+        for path in [input, output] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_metric_column() {
+        let data = b"Oslo;-3.0\nAbha;12.5\nOslo;4.5\nOslo;-0.5\n";
+        let input = fixture("metric-in", data);
+        let compressed = fixture("metric-in.zst", &zstd::encode_all(&data[..], 0).unwrap());
+        let output = fixture("metric-out", b"");
+        let config = Config {
+            input: input.to_str().unwrap().to_string(),
+            output: Some(output.clone()),
+            metric: Some(Metric::PositiveSum),
+            ..Config::default()
+        };
+        run(&Cli { command: None, config: config.clone() }).unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "\
+Abha: min=12.5 max=12.5 mean=12.5 positive_sum=12.5 count=1
+Oslo: min=-3 max=4.5 mean=0.3 positive_sum=4.5 count=3
+");
+
+        let below = Config { metric: Some(Metric::BelowZero), format: Format::Csv, ..config.clone() };
+        run(&Cli { command: None, config: below.clone() }).unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(),
+                   "station,min,mean,max,count,below_zero\nAbha,12.5,12.5,12.5,1,0\nOslo,-3.0,0.3,4.5,3,2\n");
+
+        // The column comes from the same scan whichever way the input is read
+        let expected = std::fs::read_to_string(&output).unwrap();
+        for config in [
+            Config { backend: Backend::Stream, ..below.clone() },
+            Config { partitions: Some(3), ..below.clone() },
+            Config { input: compressed.to_str().unwrap().to_string(), threads: 2, ..below.clone() },
+            Config { timeout: Some(Duration::from_secs(3600)), ..below.clone() },
+        ] {
+            run(&Cli { command: None, config }).unwrap();
+            assert_eq!(std::fs::read_to_string(&output).unwrap(), expected);
+        }
+        assert!(run(&Cli { command: None, config: Config { format: Format::Official, ..config } }).is_err());
+        for path in [input, compressed, output] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_output_is_independent_of_merge_order() {
+        let dir = std::env::temp_dir().join(format!("rs-1brc-{}-merge-order", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for shard in 0..5 {
+            let rows: String = (0..200).map(|i| format!("S{};{}.{}\n", (shard + i) % 13, i % 90 - 45, shard)).collect();
+            std::fs::write(dir.join(format!("part-{}.txt", shard)), rows).unwrap();
+        }
+        let file = dir.join("part-0.txt");
+        let (dir, file) = (dir.to_str().unwrap(), file.to_str().unwrap());
+        let output = |path: &str, config: &Config| {
+            let mut out = Vec::new();
+            write_results(&mut out, &aggregate_file(path, config).unwrap().stations, config).unwrap();
+            out
+        };
+
+        let configs = [
+            Config::default(),
+            Config { partitions: Some(9), ..Config::default() },
+            Config { backend: Backend::Stream, ..Config::default() },
+            Config { files_in_parallel: 2, ..Config::default() },
+        ];
+        for config in configs {
+            let expected = [output(file, &config), output(dir, &config)];
+            for seed in 0..8 {
+                let shuffled = Config { shuffle_merge_order: Some(seed), ..config.clone() };
+                assert_eq!([output(file, &shuffled), output(dir, &shuffled)], expected, "seed {}", seed);
+            }
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,530 @@
+use crate::{Config, CountOrder, CsvQuote, Format, RoundedStats, StationMap, WeatherStation};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// A station to print, with `None` for a listed station with no readings.
+type Row<'a> = (&'a [u8], Option<&'a WeatherStation>);
+
+/// A `--metric` column: its name and each station's value, formatted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricColumn {
+    pub name: &'static str,
+    pub values: HashMap<Vec<u8>, String>,
+}
+
+impl MetricColumn {
+    fn value(&self, station: &[u8]) -> &str {
+        self.values.get(station).map_or("-", String::as_str)
+    }
+}
+
+/// Write the stations sorted by name in the configured format.
+pub fn write_results(out: &mut impl Write, stations: &StationMap<Vec<u8>>, config: &Config) -> io::Result<()> {
+    write_output(out, stations, None, None, config)
+}
+
+/// Write only the stations in `names`, in that order, for `--stations-file`.
+pub fn write_listed(out: &mut impl Write, stations: &StationMap<Vec<u8>>, names: &[Vec<u8>], config: &Config)
+                    -> io::Result<()> {
+    write_output(out, stations, Some(names), None, config)
+}
+
+/// Write the stations in `names`, or all of them sorted by name, with a
+/// `metric` column in the verbose, table and CSV formats if given.
+pub fn write_output(out: &mut impl Write, stations: &StationMap<Vec<u8>>, names: Option<&[Vec<u8>]>,
+                    metric: Option<&MetricColumn>, config: &Config) -> io::Result<()> {
+    let mut rows: Vec<Row> = match names {
+        Some(names) => names.iter().map(|name| (name.as_slice(), stations.get(name))).collect(),
+        None => {
+            let mut rows: Vec<Row> = stations.iter().map(|(name, station)| (name.as_slice(), Some(station))).collect();
+            rows.sort_unstable_by(|a, b| a.0.cmp(b.0));
+            rows
+        }
+    };
+    write_rows(out, &mut rows, metric, config)
+}
+
+fn write_rows(out: &mut impl Write, rows: &mut [Row], metric: Option<&MetricColumn>, config: &Config) -> io::Result<()> {
+    if let Some(order) = config.count_only {
+        return write_counts(out, rows, order, config);
+    }
+    if config.integer_values {
+        return write_sums(out, rows, config);
+    }
+    let precision = config.precision();
+    let show_count = config.show_count();
+    match config.format {
+        Format::Verbose => {
+            let mut group = Group::new(config);
+            for &mut (name, station) in rows {
+                group.header(out, name)?;
+                let lossy = String::from_utf8_lossy(name);
+                match station {
+                    Some(station) => {
+                        let stats = stats(station, config);
+                        let [min, max] = [stats.min, stats.max].map(|stat| trim_zeros(format!("{:.*}", precision, stat)));
+                        write!(out, "{}: min={} max={} mean={:.*}", lossy, min, max, precision, stats.mean)?
+                    }
+                    None => write!(out, "{}: min=- max=- mean=-", lossy)?,
+                }
+                if let Some(metric) = metric {
+                    write!(out, " {}={}", metric.name, metric.value(name))?;
+                }
+                if show_count {
+                    write!(out, " count={}", format_count(count(station), config))?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Format::Official => {
+            write!(out, "{{")?;
+            for (i, &mut (name, station)) in rows.iter_mut().enumerate() {
+                let separator = if i == 0 { "" } else { ", " };
+                let name = String::from_utf8_lossy(name);
+                match station {
+                    Some(station) => {
+                        write!(out, "{}{}={:.*}", separator, name, precision, stats(station, config))?
+                    }
+                    None => write!(out, "{}{}=-/-/-", separator, name)?,
+                }
+                if show_count {
+                    write!(out, "/{}", count(station))?;
+                }
+            }
+            writeln!(out, "}}")?;
+        }
+        Format::Table => write_table(out, rows, metric, config)?,
+        Format::Csv => {
+            write!(out, "station,min,mean,max,count")?;
+            if let Some(metric) = metric {
+                write!(out, ",{}", metric.name)?;
+            }
+            writeln!(out)?;
+            for &mut (name, station) in rows {
+                let lossy = String::from_utf8_lossy(name);
+                let field = csv_field(&lossy, config.csv_quote_policy);
+                match station {
+                    Some(station) => {
+                        let RoundedStats { min, mean, max } = stats(station, config);
+                        write!(out, "{},{:.*},{:.*},{:.*},{}", field, precision, min, precision, mean, precision, max, station.count())?
+                    }
+                    None => write!(out, "{},-,-,-,0", field)?,
+                }
+                if let Some(metric) = metric {
+                    write!(out, ",{}", metric.value(name))?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Format::Raw => {
+            for &mut (name, station) in rows {
+                out.write_all(name)?;
+                match station {
+                    Some(station) => writeln!(out, " {} {} {} {}", station.min_raw(), station.sum_raw(), station.count(), station.max_raw())?,
+                    None => writeln!(out, " - 0 0 -")?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A station's min, mean and max in the input's units, rounded as
+/// `--round-mode` asks.
+fn stats(station: &WeatherStation, config: &Config) -> RoundedStats {
+    station.rounded(config.round_mode, config.decimals)
+}
+
+/// A rounded min or max as the verbose format prints it, without trailing
+/// zeros after the point: `12` and `34.2` rather than `12.0` and `34.20`.
+fn trim_zeros(mut value: String) -> String {
+    if value.contains('.') {
+        let len = value.trim_end_matches('0').trim_end_matches('.').len();
+        value.truncate(len);
+    }
+    if value == "-0" {
+        value.remove(0);
+    }
+    value
+}
+
+/// The readings of a row's station, zero for a listed station with none.
+fn count(station: Option<&WeatherStation>) -> u32 {
+    station.map_or(0, WeatherStation::count)
+}
+
+/// Write `name: sum=S count=N` lines for `--integer-values`.
+fn write_sums(out: &mut impl Write, rows: &[Row], config: &Config) -> io::Result<()> {
+    for &(name, station) in rows {
+        let sum = station.map_or(0, WeatherStation::sum_raw);
+        writeln!(out, "{}: sum={} count={}", String::from_utf8_lossy(name), sum, format_count(count(station), config))?;
+    }
+    Ok(())
+}
+
+/// Write `name: count` lines for `--count-only`, given stations sorted by
+/// name.
+fn write_counts(out: &mut impl Write, rows: &mut [Row], order: CountOrder, config: &Config) -> io::Result<()> {
+    if order == CountOrder::Count {
+        // Stable, so ties stay in name order
+        rows.sort_by_key(|&(_, station)| std::cmp::Reverse(count(station)));
+    }
+    for &mut (name, station) in rows {
+        writeln!(out, "{}: {}", String::from_utf8_lossy(name), format_count(count(station), config))?;
+    }
+    Ok(())
+}
+
+/// Write a table with the station names left-aligned and the numbers
+/// right-aligned, each column as wide as its widest cell.
+///
+/// The columns are sized in a first pass and each row formatted again to
+/// write it, so the cells of a large table are never held all at once.
+fn write_table(out: &mut impl Write, rows: &[Row], metric: Option<&MetricColumn>, config: &Config) -> io::Result<()> {
+    let mut header: Vec<_> = ["Station", "Min", "Mean", "Max"].map(String::from).into();
+    if config.show_count() {
+        header.push("Count".to_string());
+    }
+    header.extend(metric.map(|metric| metric.name.to_string()));
+    let mut widths: Vec<_> = header.iter().map(|cell| cell.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(table_cells(row, metric, config)) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    write_table_line(out, &header, &widths)?;
+    let mut group = Group::new(config);
+    for row in rows {
+        group.header(out, row.0)?;
+        write_table_line(out, &table_cells(row, metric, config), &widths)?;
+    }
+    Ok(())
+}
+
+/// The cells of one station's table row, in the columns shown.
+fn table_cells(&(name, station): &Row, metric: Option<&MetricColumn>, config: &Config) -> Vec<String> {
+    let precision = config.precision();
+    let mut cells = vec![truncate_name(&String::from_utf8_lossy(name), config.name_width)];
+    match station {
+        Some(station) => {
+            let RoundedStats { min, mean, max } = stats(station, config);
+            cells.extend([min, mean, max].map(|stat| format!("{:.*}", precision, stat)))
+        }
+        None => cells.extend(["-", "-", "-"].map(String::from)),
+    }
+    if config.show_count() {
+        cells.push(format_count(count(station), config));
+    }
+    cells.extend(metric.map(|metric| metric.value(name).to_string()));
+    cells
+}
+
+/// Write the first cell left-aligned and the rest right-aligned, each
+/// padded to its column's width.
+fn write_table_line(out: &mut impl Write, cells: &[String], widths: &[usize]) -> io::Result<()> {
+    write!(out, "{:<width$}", cells[0], width = widths[0])?;
+    for (cell, &width) in cells.iter().zip(widths).skip(1) {
+        write!(out, "  {:>width$}", cell, width = width)?;
+    }
+    writeln!(out)
+}
+
+/// Tracks the initial of the previous station for `--group-by-initial`.
+struct Group {
+    enabled: bool,
+    initial: Option<char>,
+}
+
+impl Group {
+    fn new(config: &Config) -> Self {
+        Group { enabled: config.group_by_initial, initial: None }
+    }
+
+    /// Write a header if `name` starts a new run of initials.
+    fn header(&mut self, out: &mut impl Write, name: &[u8]) -> io::Result<()> {
+        let initial = String::from_utf8_lossy(name).chars().next();
+        if self.enabled && initial != self.initial {
+            writeln!(out, "== {} ==", initial.unwrap_or(' '))?;
+            self.initial = initial;
+        }
+        Ok(())
+    }
+}
+
+/// Format a count, grouping its digits under `--group-digits`.
+fn format_count(count: u32, config: &Config) -> String {
+    let digits = count.to_string();
+    let Some(separator) = &config.group_digits else {
+        return digits;
+    };
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3 * separator.len());
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Shorten a name to `width` characters, marking the cut with an ellipsis.
+fn truncate_name(name: &str, width: usize) -> String {
+    if name.chars().count() <= width {
+        name.to_string()
+    } else {
+        name.chars().take(width - 1).chain(['…']).collect()
+    }
+}
+
+/// Quote a CSV field as RFC 4180 does, doubling any quotes within it: if it
+/// contains a delimiter, quote or line break, or always under
+/// [`CsvQuote::Always`].
+fn csv_field(field: &str, policy: CsvQuote) -> Cow<'_, str> {
+    if policy == CsvQuote::Always || field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{merge_partials, thread_runner, RoundMode};
+
+    /// Split one CSV line into fields, undoing the quoting `csv_field` applies.
+    fn parse_csv_line(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                c => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    fn render(data: &[u8], config: &Config) -> String {
+        let stations = merge_partials(vec![thread_runner(data, &Config::default()).unwrap().0]);
+        let mut out = Vec::new();
+        write_results(&mut out, &stations, config).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_count_toggle() {
+        let data = b"Hamburg;12.0\nHamburg;34.2\n";
+        let verbose = Config::default();
+        assert_eq!(render(data, &verbose), "Hamburg: min=12 max=34.2 mean=23.1 count=2\n");
+        let verbose = Config { no_count: true, ..verbose };
+        assert_eq!(render(data, &verbose), "Hamburg: min=12 max=34.2 mean=23.1\n");
+
+        let official = Config { format: Format::Official, ..Config::default() };
+        assert_eq!(render(data, &official), "{Hamburg=12.0/23.1/34.2}\n");
+        let official = Config { include_count: true, ..official };
+        assert_eq!(render(data, &official), "{Hamburg=12.0/23.1/34.2/2}\n");
+    }
+
+    #[test]
+    fn test_invalid_utf8_names_print_lossily() {
+        let config = Config { format: Format::Official, ..Config::default() };
+        assert_eq!(render(b"Bad\xff;3.0\n", &config), "{Bad\u{fffd}=3.0/3.0/3.0}\n");
+    }
+
+    #[test]
+    fn test_table_format() {
+        let data = "Hamburg;12.0\nHamburg;34.2\nSão Paulo;-5.5\nLlanfairpwllgwyngyll;1.0\n";
+        let config = Config { format: Format::Table, name_width: 12, ..Config::default() };
+        assert_eq!(render(data.as_bytes(), &config), "\
+Station        Min  Mean   Max  Count
+Hamburg       12.0  23.1  34.2      2
+Llanfairpwl…   1.0   1.0   1.0      1
+São Paulo     -5.5  -5.5  -5.5      1
+");
+    }
+
+    #[test]
+    fn test_table_matches_collected_cells() {
+        let data: String = (0..50_000).map(|i| format!("Station{};{}.{}\n", i * 7919 % 20_000, i % 200 - 100, i % 10)).collect();
+        let config = Config { format: Format::Table, max_stations: 20_000, ..Config::default() };
+        let stations = merge_partials(vec![thread_runner(data.as_bytes(), &config).unwrap().0]);
+        let mut streamed = Vec::new();
+        write_results(&mut streamed, &stations, &config).unwrap();
+
+        // Collect every cell up front and size the columns from them
+        let mut rows: Vec<Row> = stations.iter().map(|(name, station)| (name.as_slice(), Some(station))).collect();
+        rows.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let header = ["Station", "Min", "Mean", "Max", "Count"].map(String::from).to_vec();
+        let cells: Vec<_> = [header].into_iter().chain(rows.iter().map(|row| table_cells(row, None, &config))).collect();
+        let widths: Vec<_> = (0..5).map(|column| cells.iter().map(|row| row[column].chars().count()).max().unwrap()).collect();
+        let mut collected = String::new();
+        for row in &cells {
+            collected += &format!("{:<w0$}  {:>w1$}  {:>w2$}  {:>w3$}  {:>w4$}\n", row[0], row[1], row[2], row[3], row[4],
+                                  w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3], w4 = widths[4]);
+        }
+        assert_eq!(stations.len(), 20_000);
+        assert_eq!(String::from_utf8(streamed).unwrap(), collected);
+    }
+
+    #[test]
+    fn test_raw_format() {
+        let data = "Hamburg;12.0\nSt. John's;-0.3\nHamburg;34.2\nHamburg;-1.1\n".as_bytes();
+        let config = Config { format: Format::Raw, ..Config::default() };
+        let out = render(data, &config);
+        assert_eq!(out, "Hamburg -11 451 3 342\nSt. John's -3 -3 1 -3\n");
+
+        // The integers give back the stats exactly
+        let stations = merge_partials(vec![thread_runner(data, &Config::default()).unwrap().0]);
+        for line in out.lines() {
+            let mut fields = line.rsplitn(5, ' ');
+            let [max, count, sum, min] = [(); 4].map(|()| fields.next().unwrap().parse::<i64>().unwrap());
+            let station = &stations[fields.next().unwrap().as_bytes()];
+            assert_eq!(min as f32 / 10.0, station.min());
+            assert_eq!(max as f32 / 10.0, station.max());
+            assert_eq!(sum as f64 / count as f64 / 10.0, station.mean());
+            assert_eq!(count, station.count() as i64);
+        }
+    }
+
+    #[test]
+    fn test_group_by_initial() {
+        let data = "Bulawayo;1.0\nAccra;2.0\nÅlesund;3.0\nBaghdad;4.0\nCairo;5.0\nAbha;6.0\n".as_bytes();
+        let config = Config { group_by_initial: true, no_count: true, ..Config::default() };
+        assert_eq!(render(data, &config), "\
+== A ==
+Abha: min=6 max=6 mean=6.0
+Accra: min=2 max=2 mean=2.0
+== B ==
+Baghdad: min=4 max=4 mean=4.0
+Bulawayo: min=1 max=1 mean=1.0
+== C ==
+Cairo: min=5 max=5 mean=5.0
+== Å ==
+Ålesund: min=3 max=3 mean=3.0
+");
+
+        let config = Config { format: Format::Table, ..config };
+        let table = render(b"Oslo;1.0\nAbha;2.0\n", &config);
+        let lines: Vec<_> = table.lines().map(str::trim_end).collect();
+        assert_eq!(lines, ["Station  Min  Mean  Max", "== A ==", "Abha     2.0   2.0  2.0", "== O ==", "Oslo     1.0   1.0  1.0"]);
+    }
+
+    #[test]
+    fn test_count_only_output() {
+        let data = b"Oslo;1.0\nAbha;2.0\nCairo;3.0\nOslo;4.0\nCairo;5.0\nOslo;6.0\nBern;7.0\n";
+        let config = Config { count_only: Some(CountOrder::Name), ..Config::default() };
+        assert_eq!(render(data, &config), "Abha: 1\nBern: 1\nCairo: 2\nOslo: 3\n");
+        let config = Config { count_only: Some(CountOrder::Count), ..Config::default() };
+        assert_eq!(render(data, &config), "Oslo: 3\nCairo: 2\nAbha: 1\nBern: 1\n");
+    }
+
+    #[test]
+    fn test_group_digits() {
+        let grouped = Config { group_digits: Some(",".to_string()), ..Config::default() };
+        assert_eq!(format_count(1234567, &grouped), "1,234,567");
+        assert_eq!(format_count(123456, &grouped), "123,456");
+        assert_eq!(format_count(999, &grouped), "999");
+        assert_eq!(format_count(0, &grouped), "0");
+        assert_eq!(format_count(1234567, &Config { group_digits: Some("_".to_string()), ..Config::default() }), "1_234_567");
+        assert_eq!(format_count(1234567, &Config::default()), "1234567");
+
+        let data = "A;1.0\n".repeat(1234);
+        assert_eq!(render(data.as_bytes(), &grouped), "A: min=1 max=1 mean=1.0 count=1,234\n");
+        let csv = Config { format: Format::Csv, ..grouped };
+        assert!(render(data.as_bytes(), &csv).ends_with(",1234\n"));
+    }
+
+    #[test]
+    fn test_listed_stations() {
+        let stations = merge_partials(vec![thread_runner(b"Oslo;1.0\nAbha;2.0\nOslo;3.0\n", &Config::default()).unwrap().0]);
+        let names = [b"Oslo".to_vec(), b"Cairo".to_vec()];
+        let render = |config: Config| {
+            let mut out = Vec::new();
+            write_listed(&mut out, &stations, &names, &config).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(render(Config { format: Format::Official, ..Config::default() }), "{Oslo=1.0/2.0/3.0, Cairo=-/-/-}\n");
+        assert_eq!(render(Config { format: Format::Csv, ..Config::default() }),
+                   "station,min,mean,max,count\nOslo,1.0,2.0,3.0,2\nCairo,-,-,-,0\n");
+        assert_eq!(render(Config { format: Format::Raw, ..Config::default() }), "Oslo 10 40 2 30\nCairo - 0 0 -\n");
+        assert_eq!(render(Config { format: Format::Table, ..Config::default() }), "\
+Station  Min  Mean  Max  Count
+Oslo     1.0   2.0  3.0      2
+Cairo      -     -    -      0
+");
+        assert_eq!(render(Config { count_only: Some(CountOrder::Count), ..Config::default() }), "Oslo: 2\nCairo: 0\n");
+    }
+
+    #[test]
+    fn test_metric_column_in_table() {
+        let stations = merge_partials(vec![thread_runner(b"Oslo;1.0\nAbha;2.0\nOslo;3.0\n", &Config::default()).unwrap().0]);
+        let metric = MetricColumn { name: "below_zero", values: [(b"Oslo".to_vec(), "12".to_string())].into() };
+        let config = Config { format: Format::Table, no_count: true, ..Config::default() };
+        let mut out = Vec::new();
+        write_output(&mut out, &stations, None, Some(&metric), &config).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+Station  Min  Mean  Max  below_zero
+Abha     2.0   2.0  2.0           -
+Oslo     1.0   2.0  3.0          12
+");
+    }
+
+    #[test]
+    fn test_csv_format() {
+        let data = b"Hamburg;12.0\nWashington, D.C.;8.9\nHamburg;34.2\n";
+        let config = Config { format: Format::Csv, output_precision: Some(2), ..Config::default() };
+        let out = render(data, &config);
+        let rows: Vec<_> = out.lines().map(parse_csv_line).collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], ["station", "min", "mean", "max", "count"]);
+        assert_eq!(rows[1], ["Hamburg", "12.00", "23.10", "34.20", "2"]);
+        assert_eq!(rows[2], ["Washington, D.C.", "8.90", "8.90", "8.90", "1"]);
+    }
+
+    #[test]
+    fn test_csv_quote_policy() {
+        let data = "Hamburg;12.0\nThe \"Big\" Apple, NY;8.9\n".as_bytes();
+        let minimal = render(data, &Config { format: Format::Csv, ..Config::default() });
+        assert_eq!(minimal, "station,min,mean,max,count\nHamburg,12.0,12.0,12.0,1\n\"The \"\"Big\"\" Apple, NY\",8.9,8.9,8.9,1\n");
+        let always = render(data, &Config { format: Format::Csv, csv_quote_policy: CsvQuote::Always, ..Config::default() });
+        assert_eq!(always, "station,min,mean,max,count\n\"Hamburg\",12.0,12.0,12.0,1\n\"The \"\"Big\"\" Apple, NY\",8.9,8.9,8.9,1\n");
+
+        for out in [minimal, always] {
+            let names: Vec<_> = out.lines().skip(1).map(|line| parse_csv_line(line).remove(0)).collect();
+            assert_eq!(names, ["Hamburg", "The \"Big\" Apple, NY"]);
+        }
+    }
+
+    #[test]
+    fn test_round_mode_applies_to_every_format() {
+        let data = b"Oslo;1.2\nOslo;1.3\n";
+        for (mode, mean) in [(RoundMode::Ieee, "1.2"), (RoundMode::HalfUp, "1.3"), (RoundMode::Trunc, "1.2")] {
+            let config = Config { round_mode: mode, ..Config::default() };
+            assert_eq!(render(data, &Config { format: Format::Official, ..config.clone() }), format!("{{Oslo=1.2/{}/1.3}}\n", mean));
+            let csv = render(data, &Config { format: Format::Csv, ..config.clone() });
+            assert_eq!(csv, format!("station,min,mean,max,count\nOslo,1.2,{},1.3,2\n", mean));
+            let table = render(data, &Config { format: Format::Table, no_count: true, ..config.clone() });
+            assert!(table.ends_with(&format!("Oslo     1.2   {}  1.3\n", mean)), "{}", table);
+            let verbose = render(data, &Config { format: Format::Verbose, ..config });
+            assert_eq!(verbose, format!("Oslo: min=1.2 max=1.3 mean={} count=2\n", mean));
+        }
+
+        // Verbose rounds min and max too, to the output precision
+        let config = Config { format: Format::Verbose, decimals: 2, output_precision: Some(1), ..Config::default() };
+        let stations = merge_partials(vec![thread_runner(b"A;1.25\nA;-0.04\n", &config).unwrap().0]);
+        let mut out = Vec::new();
+        write_results(&mut out, &stations, &config).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "A: min=0 max=1.3 mean=0.6 count=2\n");
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{try_parse_decimals, try_parse_measurement, Measurement};
+
+/// Iterator over the records of a slice of the input, yielding each station
+/// name with its reading in tenths of a degree.
+///
+/// A final line without a trailing newline is still a record. Lines that
+/// don't parse are yielded as [`Malformed`] so the caller can decide whether
+/// to stop. With `VALUE_FIRST` records are `value;name` rather than
+/// `name;value`.
+///
+/// Lines are split at their first `;`, so a name containing one is cut short
+/// and the rest taken as the value, which then doesn't parse. With
+/// `LAST_DELIMITER` they are split at their last `;` instead, keeping any
+/// earlier ones in the name. With `TRIM` any NULs and spaces padding the end
+/// of a line are dropped before it is split. Readings have one fractional
+/// digit unless [`with_decimals`](RecordIter::with_decimals) says otherwise.
+#[derive(Debug, Clone)]
+pub struct RecordIter<'a, const VALUE_FIRST: bool = false, const LAST_DELIMITER: bool = false, const TRIM: bool = false> {
+    data: &'a [u8],
+    rest: &'a [u8],
+    decimals: usize,
+}
+
+/// A line that isn't a valid record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Malformed<'a> {
+    /// Offset of the line from the start of the slice being iterated.
+    pub offset: usize,
+    pub line: &'a [u8],
+}
+
+impl<'a> RecordIter<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self::with_layout(data)
+    }
+}
+
+impl<'a, const VALUE_FIRST: bool, const LAST_DELIMITER: bool, const TRIM: bool> RecordIter<'a, VALUE_FIRST, LAST_DELIMITER, TRIM> {
+    pub fn with_layout(data: &'a [u8]) -> Self {
+        RecordIter { data, rest: data, decimals: 1 }
+    }
+
+    /// Expect `decimals` digits after the point, yielding readings in units
+    /// of the last one.
+    pub fn with_decimals(self, decimals: usize) -> Self {
+        RecordIter { decimals, ..self }
+    }
+}
+
+impl<'a, const VALUE_FIRST: bool, const LAST_DELIMITER: bool, const TRIM: bool> Iterator
+    for RecordIter<'a, VALUE_FIRST, LAST_DELIMITER, TRIM>
+{
+    type Item = Result<(&'a [u8], Measurement), Malformed<'a>>;
+
+    // Scan with memchr and split_at rather than indexing byte by byte, so the
+    // loops carry no per-byte bounds checks
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let offset = self.data.len() - self.rest.len();
+
+        // Split off the next line
+        let line_end = memchr::memchr(b'\n', self.rest).unwrap_or(self.rest.len());
+        let (line, tail) = self.rest.split_at(line_end);
+        self.rest = tail.get(1..).unwrap_or_default();
+        let line = if TRIM { trim_padding(line) } else { line };
+
+        // Split the line into the name and the reading
+        let malformed = Malformed { offset, line };
+        let delimiter = if LAST_DELIMITER { memchr::memrchr(b';', line) } else { memchr::memchr(b';', line) };
+        let Some(delimiter) = delimiter else {
+            return Some(Err(malformed));
+        };
+        let (first, second) = (&line[..delimiter], &line[delimiter + 1..]);
+        let (name, value) = if VALUE_FIRST { (second, first) } else { (first, second) };
+        // Every record takes the same side, so the branch predicts perfectly
+        let measurement = match self.decimals {
+            1 => try_parse_measurement(value),
+            decimals => try_parse_decimals(value, decimals),
+        };
+        Some(match measurement {
+            Some(measurement) if !name.is_empty() => Ok((name, measurement)),
+            _ => Err(malformed),
+        })
+    }
+}
+
+/// `line` without the NULs and spaces padding its end, for `--trim`.
+#[inline(always)]
+pub fn trim_padding(line: &[u8]) -> &[u8] {
+    let end = line.iter().rposition(|&b| b != 0 && b != b' ').map_or(0, |last| last + 1);
+    &line[..end]
+}
+
+/// Offset of the first record starting at or after `offset`: `offset` itself
+/// if it is 0 or follows a newline, otherwise just past the next newline, or
+/// the end of `data` if there is none.
+///
+/// This and [`prev_record_start`] are the one definition of a record
+/// boundary for everything that splits or cuts the input at a byte offset.
+#[inline]
+pub fn next_record_start(data: &[u8], offset: usize) -> usize {
+    if offset == 0 || offset >= data.len() {
+        return offset.min(data.len());
+    }
+    match memchr::memchr(b'\n', &data[offset - 1..]) {
+        Some(newline) => offset + newline,
+        None => data.len(),
+    }
+}
+
+/// Offset of the last record starting at or before `offset`: `offset` itself
+/// if it is 0 or follows a newline, otherwise just past the last newline
+/// before it, or 0 if there is none.
+#[inline]
+pub fn prev_record_start(data: &[u8], offset: usize) -> usize {
+    let offset = offset.min(data.len());
+    memchr::memrchr(b'\n', &data[..offset]).map_or(0, |newline| newline + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records() {
+        let records: Vec<_> = RecordIter::new(b"Hamburg;12.0\nBulawayo;-8.9\nSt. John's;0.5\n").collect();
+        assert_eq!(records, [Ok((&b"Hamburg"[..], 120)), Ok((&b"Bulawayo"[..], -89)), Ok((&b"St. John's"[..], 5))]);
+
+        let records: Vec<_> = RecordIter::<true>::with_layout(b"12.0;Hamburg\n").collect();
+        assert_eq!(records, [Ok((&b"Hamburg"[..], 120))]);
+    }
+
+    #[test]
+    fn test_last_delimiter() {
+        let records: Vec<_> = RecordIter::new(b"A;B;10.0\n").collect();
+        assert_eq!(records, [Err(Malformed { offset: 0, line: b"A;B;10.0" })]);
+
+        let records: Vec<_> = RecordIter::<false, true>::with_layout(b"A;B;10.0\nC;-1.5\nD;E;\n").collect();
+        assert_eq!(records, [Ok((&b"A;B"[..], 100)), Ok((&b"C"[..], -15)), Err(Malformed { offset: 16, line: b"D;E;" })]);
+    }
+
+    #[test]
+    fn test_trim_padding() {
+        let data = b"Berlin;10.1\0\0\nOslo;-2.0  \0 \nRome;3.0\n\0\0\n";
+        let records: Vec<_> = RecordIter::<false, false, true>::with_layout(data).collect();
+        assert_eq!(records, [Ok((&b"Berlin"[..], 101)), Ok((&b"Oslo"[..], -20)), Ok((&b"Rome"[..], 30)),
+                             Err(Malformed { offset: 37, line: b"" })]);
+        assert_eq!(RecordIter::new(data).next(), Some(Err(Malformed { offset: 0, line: b"Berlin;10.1\0\0" })));
+
+        let records: Vec<_> = RecordIter::<true, false, true>::with_layout(b"10.1;Berlin\0\0\n").collect();
+        assert_eq!(records, [Ok((&b"Berlin"[..], 101))]);
+    }
+
+    #[test]
+    fn test_trailing_partial_line() {
+        let records: Vec<_> = RecordIter::new(b"A;1.0\nB;2.5").collect();
+        assert_eq!(records, [Ok((&b"A"[..], 10)), Ok((&b"B"[..], 25))]);
+
+        let records: Vec<_> = RecordIter::new(b"A;1.0\nB;2.").collect();
+        assert_eq!(records, [Ok((&b"A"[..], 10)), Err(Malformed { offset: 6, line: b"B;2." })]);
+    }
+
+    #[test]
+    fn test_record_starts() {
+        let data = b"A;1.0\nBB;-2.0\nC;3.5";
+        // Offsets at, just after and mid-way through records, and past the end
+        for (offset, next, prev) in [(0, 0, 0), (1, 6, 0), (5, 6, 0), (6, 6, 6), (7, 14, 6), (13, 14, 6), (14, 14, 14),
+                                     (15, 19, 14), (18, 19, 14), (19, 19, 14), (99, 19, 14)] {
+            assert_eq!(next_record_start(data, offset), next, "next from {}", offset);
+            assert_eq!(prev_record_start(data, offset), prev, "prev from {}", offset);
+        }
+        assert_eq!(next_record_start(b"no newline", 3), 10);
+        assert_eq!(prev_record_start(b"no newline", 3), 0);
+        assert_eq!((next_record_start(b"", 0), prev_record_start(b"", 0)), (0, 0));
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        let records: Vec<_> = RecordIter::new(b"no delimiter\n;1.0\nA;\n\nB;3.0\n").collect();
+        assert_eq!(records, [
+            Err(Malformed { offset: 0, line: b"no delimiter" }),
+            Err(Malformed { offset: 13, line: b";1.0" }),
+            Err(Malformed { offset: 18, line: b"A;" }),
+            Err(Malformed { offset: 21, line: b"" }),
+            Ok((&b"B"[..], 30)),
+        ]);
+    }
+}
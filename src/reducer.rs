@@ -0,0 +1,127 @@
+use crate::{Measurement, StationHasher, WeatherStation};
+use std::collections::HashMap;
+
+/// Per-station state folded from its readings, which the record loops are
+/// generic over.
+///
+/// [`WeatherStation`]'s min, max, sum and count make up the results; other
+/// reducers compute the `--metric` column, paired with it so both come from
+/// one scan, and [`reduce`](crate::reduce) aggregates with any of them. `Default` is the state with no readings.
+/// Partitions are merged in no particular order, so `merge` must be
+/// commutative and associative.
+pub trait Reducer: Default {
+    fn add(&mut self, measurement: Measurement);
+
+    fn merge(&mut self, other: &Self);
+}
+
+/// Stations keyed by name, each with its reducer's state.
+pub type ReducerMap<K, R> = HashMap<K, R, StationHasher>;
+
+impl Reducer for WeatherStation {
+    #[inline(always)]
+    fn add(&mut self, measurement: Measurement) {
+        self.add_measurement(measurement);
+    }
+
+    #[inline(always)]
+    fn merge(&mut self, other: &Self) {
+        WeatherStation::merge(self, other);
+    }
+}
+
+/// Two reducers fed the same readings, as `--metric` keeps its reducer
+/// beside each station's [`WeatherStation`] in a single scan.
+impl<A: Reducer, B: Reducer> Reducer for (A, B) {
+    #[inline(always)]
+    fn add(&mut self, measurement: Measurement) {
+        self.0.add(measurement);
+        self.1.add(measurement);
+    }
+
+    #[inline(always)]
+    fn merge(&mut self, other: &Self) {
+        self.0.merge(&other.0);
+        self.1.merge(&other.1);
+    }
+}
+
+/// Sum of the readings above zero, in tenths of a degree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PositiveSum(pub i64);
+
+impl Reducer for PositiveSum {
+    #[inline(always)]
+    fn add(&mut self, measurement: Measurement) {
+        self.0 += measurement.max(0) as i64;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.0 += other.0;
+    }
+}
+
+/// Number of readings below zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BelowZero(pub u32);
+
+impl Reducer for BelowZero {
+    #[inline(always)]
+    fn add(&mut self, measurement: Measurement) {
+        self.0 += (measurement < 0) as u32;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.0 += other.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{partition_slice, reduce, Config};
+
+    /// Every reading, kept sorted as merging doesn't preserve their order.
+    #[derive(Debug, Default, PartialEq)]
+    struct Readings(Vec<Measurement>);
+
+    impl Reducer for Readings {
+        fn add(&mut self, measurement: Measurement) {
+            let at = self.0.partition_point(|&m| m < measurement);
+            self.0.insert(at, measurement);
+        }
+
+        fn merge(&mut self, other: &Self) {
+            other.0.iter().for_each(|&measurement| self.add(measurement));
+        }
+    }
+
+    const DATA: &[u8] = b"Oslo;-3.0\nAbha;12.5\nOslo;4.5\nOslo;-0.5\nAbha;-1.0\nOslo;4.5\nCairo;0.0\n";
+
+    #[test]
+    fn test_custom_reducer() {
+        for partitions in [1, 2, 5] {
+            let stations = reduce::<Readings>(&partition_slice(DATA, partitions), &Config::default()).unwrap();
+            assert_eq!(stations.len(), 3);
+            assert_eq!(stations[&b"Oslo"[..]], Readings(vec![-30, -5, 45, 45]), "{} partitions", partitions);
+            assert_eq!(stations[&b"Abha"[..]], Readings(vec![-10, 125]));
+            assert_eq!(stations[&b"Cairo"[..]], Readings(vec![0]));
+        }
+    }
+
+    #[test]
+    fn test_built_in_reducers() {
+        let partitions = partition_slice(DATA, 3);
+        let sums = reduce::<PositiveSum>(&partitions, &Config::default()).unwrap();
+        assert_eq!([&b"Oslo"[..], b"Abha", b"Cairo"].map(|name| sums[name]), [PositiveSum(90), PositiveSum(125), PositiveSum(0)]);
+        let below = reduce::<BelowZero>(&partitions, &Config::default()).unwrap();
+        assert_eq!([&b"Oslo"[..], b"Abha", b"Cairo"].map(|name| below[name]), [BelowZero(2), BelowZero(1), BelowZero(0)]);
+
+        // The main results go through the same loop, alone or with a metric
+        let stations = reduce::<WeatherStation>(&partitions, &Config::default()).unwrap();
+        let oslo = stations[&b"Oslo"[..]];
+        assert_eq!((oslo.min_raw(), oslo.max_raw(), oslo.sum_raw(), oslo.count()), (-30, 45, 55, 4));
+        let pairs = reduce::<(WeatherStation, BelowZero)>(&partitions, &Config::default()).unwrap();
+        assert_eq!(pairs[&b"Oslo"[..]], (oslo, BelowZero(2)));
+    }
+}
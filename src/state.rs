@@ -0,0 +1,208 @@
+use crate::{Config, Measurement, StationHasher, StationMap, WeatherStation};
+use anyhow::{bail, Context, Result};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// Identifies a saved state file, followed by the format version.
+const MAGIC: &[u8; 4] = b"1BRC";
+const VERSION: u8 = 1;
+/// A `--checkpoint-file`: a version 1 state with the length of input it
+/// covers after the version byte.
+const CHECKPOINT_VERSION: u8 = 2;
+
+/// Write merged results in the binary state format.
+///
+/// After the magic and version byte come the station count as a `u64`, then
+/// for each station in name order its name length (`u32`) and name, and its
+/// raw `min`, `max` (`i16`), `sum` (`i64`) and `count` (`u32`). All integers
+/// are little-endian.
+pub fn write_state(out: &mut impl Write, stations: &StationMap<Vec<u8>>) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    write_stations(out, stations)
+}
+
+/// Write the results of the first `offset` bytes of the input as a
+/// checkpoint, which a resumed run continues from.
+pub fn write_checkpoint(out: &mut impl Write, stations: &StationMap<Vec<u8>>, offset: usize) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[CHECKPOINT_VERSION])?;
+    out.write_all(&(offset as u64).to_le_bytes())?;
+    write_stations(out, stations)
+}
+
+fn write_stations(out: &mut impl Write, stations: &StationMap<Vec<u8>>) -> io::Result<()> {
+    let mut sorted: Vec<_> = stations.iter().collect();
+    sorted.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+    out.write_all(&(sorted.len() as u64).to_le_bytes())?;
+    for (name, station) in sorted {
+        out.write_all(&(name.len() as u32).to_le_bytes())?;
+        out.write_all(name)?;
+        // Every temperature fits in the format's i16, whatever the width of
+        // `Measurement`, and --integer-values, which may not, can't be saved,
+        // so the cast is only needed with wide-measurements
+        #[allow(clippy::unnecessary_cast)]
+        let (min, max) = (station.min as i16, station.max as i16);
+        out.write_all(&min.to_le_bytes())?;
+        out.write_all(&max.to_le_bytes())?;
+        out.write_all(&station.sum.to_le_bytes())?;
+        out.write_all(&station.count.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Read results written by [`write_state`], or the stations of a checkpoint.
+pub fn read_state(input: &mut impl Read, config: &Config) -> Result<StationMap<Vec<u8>>> {
+    read_checkpoint(input, config).map(|(stations, _)| stations)
+}
+
+/// Read results written by [`write_state`] or [`write_checkpoint`], with the
+/// length of input they cover: 0 if they aren't a checkpoint, as they then
+/// aren't tied to a position in it.
+pub fn read_checkpoint(input: &mut impl Read, config: &Config) -> Result<(StationMap<Vec<u8>>, usize)> {
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("Not a saved state file");
+    }
+    let offset = match read_array(input)? {
+        [VERSION] => 0,
+        [CHECKPOINT_VERSION] => usize::try_from(u64::from_le_bytes(read_array(input)?))?,
+        [version] => bail!("Unsupported state file version {} (expected {} or {})", version, VERSION, CHECKPOINT_VERSION),
+    };
+
+    let len = u64::from_le_bytes(read_array(input)?);
+    let mut stations = StationMap::with_hasher(StationHasher::from_config(config));
+    for _ in 0..len {
+        // Check the length before allocating it, as the file may be corrupt
+        let name_len = u32::from_le_bytes(read_array(input)?) as usize;
+        if name_len > config.max_record_len {
+            bail!("Station name of {} bytes is longer than --max-record-len ({})", name_len, config.max_record_len);
+        }
+        let mut name = vec![0; name_len];
+        input.read_exact(&mut name)?;
+        let station = WeatherStation {
+            min: Measurement::from(i16::from_le_bytes(read_array(input)?)),
+            max: Measurement::from(i16::from_le_bytes(read_array(input)?)),
+            sum: i64::from_le_bytes(read_array(input)?),
+            count: u32::from_le_bytes(read_array(input)?),
+        };
+        if stations.contains_key(&name) {
+            bail!("Station {:?} appears more than once", String::from_utf8_lossy(&name));
+        }
+        stations.insert(name, station);
+    }
+    Ok((stations, offset))
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Whether `path` starts with the state file magic.
+pub fn is_state_file(path: &str) -> Result<bool> {
+    if crate::http::is_url(path) || Path::new(path).is_dir() {
+        return Ok(false);
+    }
+    let file = File::open(path).with_context(|| format!("Failed to open file: {}", path))?;
+    let mut magic = Vec::with_capacity(MAGIC.len());
+    file.take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+    Ok(magic == MAGIC)
+}
+
+pub fn read_state_file(path: &str, config: &Config) -> Result<StationMap<Vec<u8>>> {
+    read_checkpoint_file(path, config).map(|(stations, _)| stations)
+}
+
+pub fn read_checkpoint_file(path: &str, config: &Config) -> Result<(StationMap<Vec<u8>>, usize)> {
+    let file = File::open(path).with_context(|| format!("Failed to open file: {}", path))?;
+    read_checkpoint(&mut BufReader::new(file), config).with_context(|| format!("Failed to read state file: {}", path))
+}
+
+pub fn write_state_file(path: &Path, stations: &StationMap<Vec<u8>>) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    write_state(&mut out, stations)
+        .and_then(|()| out.flush())
+        .with_context(|| format!("Failed to write state file: {}", path.display()))
+}
+
+/// Replace `path` with a checkpoint, written to a temporary file beside it
+/// and renamed over it, so a crash mid-write leaves the last one whole.
+pub fn write_checkpoint_file(path: &Path, stations: &StationMap<Vec<u8>>, offset: usize) -> Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = Path::new(&temporary);
+    let file = File::create(temporary).with_context(|| format!("Failed to create file: {}", temporary.display()))?;
+    let mut out = BufWriter::new(file);
+    write_checkpoint(&mut out, stations, offset)
+        .and_then(|()| out.flush())
+        .with_context(|| format!("Failed to write checkpoint: {}", temporary.display()))?;
+    std::fs::rename(temporary, path).with_context(|| format!("Failed to replace checkpoint: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aggregate, partition_slice};
+
+    #[test]
+    fn test_state_round_trip() {
+        let data = "Hamburg;12.0\nBulawayo;-8.9\nSão Paulo;99.9\nHamburg;-99.9\n".as_bytes();
+        let stations = aggregate(&partition_slice(data, 2), &Config::default()).unwrap().stations;
+        let mut saved = Vec::new();
+        write_state(&mut saved, &stations).unwrap();
+        assert_eq!(&saved[..5], b"1BRC\x01");
+        assert_eq!(read_state(&mut saved.as_slice(), &Config::default()).unwrap(), stations);
+
+        // Truncated files and other versions are rejected
+        assert!(read_state(&mut &saved[..saved.len() - 1], &Config::default()).is_err());
+        saved[4] = 3;
+        let err = read_state(&mut saved.as_slice(), &Config::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported state file version 3 (expected 1 or 2)");
+    }
+
+    #[test]
+    fn test_corrupt_state_is_rejected() {
+        let data = b"Bulawayo;-8.9\nHamburg;12.0\n";
+        let stations = aggregate(&partition_slice(data, 1), &Config::default()).unwrap().stations;
+        let mut saved = Vec::new();
+        write_state(&mut saved, &stations).unwrap();
+
+        // A name length no record could have fails before it is allocated
+        let mut corrupt = saved.clone();
+        corrupt[13..17].copy_from_slice(&u32::MAX.to_le_bytes());
+        let config = Config { max_record_len: 1000, ..Config::default() };
+        let err = read_state(&mut corrupt.as_slice(), &config).unwrap_err();
+        assert_eq!(err.to_string(), "Station name of 4294967295 bytes is longer than --max-record-len (1000)");
+
+        // As does a station saved twice, whose readings would otherwise be
+        // replaced by the second copy's
+        let bulawayo = &saved[13..13 + 4 + 8 + 16];
+        let duplicated = [&saved[..5], &2u64.to_le_bytes(), bulawayo, bulawayo].concat();
+        let err = read_state(&mut duplicated.as_slice(), &Config::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Station \"Bulawayo\" appears more than once");
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let data = b"Hamburg;12.0\nBulawayo;-8.9\nHamburg;-99.9\n";
+        let stations = aggregate(&partition_slice(data, 2), &Config::default()).unwrap().stations;
+        let mut saved = Vec::new();
+        write_checkpoint(&mut saved, &stations, 27).unwrap();
+        assert_eq!(&saved[..5], b"1BRC\x02");
+        assert_eq!(read_checkpoint(&mut saved.as_slice(), &Config::default()).unwrap(), (stations.clone(), 27));
+        // Anything that reads a state takes a checkpoint's stations
+        assert_eq!(read_state(&mut saved.as_slice(), &Config::default()).unwrap(), stations);
+
+        let mut plain = Vec::new();
+        write_state(&mut plain, &stations).unwrap();
+        assert_eq!(read_checkpoint(&mut plain.as_slice(), &Config::default()).unwrap().1, 0);
+    }
+}
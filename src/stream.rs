@@ -0,0 +1,358 @@
+use crate::{
+    check_station_count, longest_name, map_bytes, merge_partial, merge_stations, run_partition, shuffle_merge_order,
+    records, thread_cpu_time, Aggregation, Config, Deadline, RunContext, Skipped, StationHasher, StationMap, ThreadStats,
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    fs::File,
+    io,
+    ops::Range,
+    os::unix::fs::FileExt,
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Size of each read from the file.
+const BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Smallest read `--memory-limit` may shrink the blocks to, below which the
+/// per-read overhead dominates.
+const MIN_BLOCK_SIZE: usize = 4096;
+
+/// Chunks queued per worker. Along with the block size this bounds the memory
+/// in flight to roughly `threads * CHUNKS_PER_WORKER * BLOCK_SIZE`, while
+/// keeping enough read ahead that workers rarely wait on the disk.
+const CHUNKS_PER_WORKER: usize = 2;
+
+/// A run of complete records and the file offset it starts at.
+struct Chunk {
+    offset: usize,
+    data: Vec<u8>,
+}
+
+/// Aggregate a file without mapping it.
+///
+/// The file is split into `config.io_threads` newline-aligned regions, each
+/// read in blocks by its own reader thread. Readers queue chunks of complete
+/// records on a bounded channel, and `config.threads` workers parse whichever
+/// chunk is next, so reading overlaps parsing.
+///
+/// Under `--memory-limit` the blocks shrink so the chunks in flight fit in
+/// the limit.
+pub fn aggregate_stream(file: &File, config: &Config, context: &RunContext) -> Result<Aggregation> {
+    let block_size = match config.memory_limit {
+        Some(limit) => block_size_within(limit, config)?,
+        None => BLOCK_SIZE,
+    };
+    aggregate_stream_with(file, config, context, block_size)
+}
+
+/// The largest block size, up to [`BLOCK_SIZE`], that keeps the blocks in
+/// flight within `limit` bytes: the queued chunks, one being parsed by each
+/// worker and one being filled by each reader.
+fn block_size_within(limit: usize, config: &Config) -> Result<usize> {
+    let buffers = config.threads * (CHUNKS_PER_WORKER + 1) + config.io_threads;
+    let block_size = (limit / buffers).min(BLOCK_SIZE);
+    if block_size < MIN_BLOCK_SIZE {
+        bail!("Memory limit of {} bytes is too small: {} threads and {} readers need at least {} bytes",
+              limit, config.threads, config.io_threads, buffers * MIN_BLOCK_SIZE);
+    }
+    Ok(block_size)
+}
+
+fn aggregate_stream_with(file: &File, config: &Config, context: &RunContext, block_size: usize) -> Result<Aggregation> {
+    let metadata = file.metadata()?;
+    if metadata.is_dir() {
+        bail!("Expected a file, got a directory");
+    }
+    let len = usize::try_from(metadata.len()).context("File is too large to read on this platform")?;
+    let len = match config.first_n_bytes {
+        Some(limit) if limit < len => prev_record_start(file, limit).context("Failed to read file")?,
+        _ => len,
+    };
+    let regions = split_regions(file, len, config.io_threads).context("Failed to read file")?;
+    advise(file, 0..0, Advice::Sequential);
+    let (sender, receiver) = sync_channel(config.threads * CHUNKS_PER_WORKER);
+    let receiver = Mutex::new(receiver);
+
+    let (read_results, worker_results) = std::thread::scope(|scope| {
+        let readers: Vec<_> = regions
+            .into_iter()
+            .map(|region| {
+                let sender = sender.clone();
+                scope.spawn(move || read_region(file, region, block_size, sender, config, context.deadline.as_ref()))
+            })
+            .collect();
+        drop(sender);
+
+        let receiver = &receiver;
+        let workers: Vec<_> = (0..config.threads)
+            .map(|thread| scope.spawn(move || run_worker(thread, receiver, config, context)))
+            .collect();
+
+        let read_results: Vec<_> = readers.into_iter().map(|r| r.join().expect("reader thread panicked")).collect();
+        let worker_results: Vec<_> = workers.into_iter().map(|w| w.join().expect("worker thread panicked")).collect();
+        (read_results, worker_results)
+    });
+    read_results.into_iter().collect::<Result<()>>()?;
+
+    let mut partials = Vec::with_capacity(config.threads);
+    let mut threads = Vec::with_capacity(config.threads);
+    let mut skipped = Skipped::default();
+    let mut partial_map_bytes = 0;
+    let mut cpu_times = Vec::with_capacity(config.threads);
+    for result in worker_results {
+        let (partial, stats, worker_skipped, cpu_time) = result?;
+        partial_map_bytes += map_bytes(&partial);
+        partials.push(partial);
+        threads.push(stats);
+        skipped.merge(&worker_skipped);
+        cpu_times.push(cpu_time);
+    }
+    shuffle_merge_order(&mut partials, config);
+    let mut stations = StationMap::with_hasher(StationHasher::from_config(config));
+    for partial in partials {
+        merge_stations(&mut stations, partial);
+    }
+    check_station_count(stations.len(), config)?;
+    let cpu_times = cpu_times.into_iter().collect::<Option<_>>().unwrap_or_default();
+    Ok(Aggregation { stations, threads, skipped, partial_map_bytes, cpu_times })
+}
+
+/// Split the file into `count` regions that each start on a record boundary.
+fn split_regions(file: &File, len: usize, count: usize) -> io::Result<Vec<Range<usize>>> {
+    let region_size = len.div_ceil(count);
+    let mut starts = Vec::with_capacity(count + 1);
+    for i in 0..count {
+        starts.push(next_record_start(file, (i * region_size).min(len), len)?);
+    }
+    starts.push(len);
+    Ok(starts.windows(2).map(|w| w[0]..w[1].max(w[0])).collect())
+}
+
+/// Offset of the first record starting at or after `offset`, as
+/// [`records::next_record_start`] finds in memory.
+fn next_record_start(file: &File, offset: usize, len: usize) -> io::Result<usize> {
+    if offset == 0 || offset >= len {
+        return Ok(offset.min(len));
+    }
+    // The record at `offset` only starts there if a newline precedes it, so
+    // read from the byte before and look for a start after the first byte
+    let mut pos = offset - 1;
+    let mut buf = [0; 4096];
+    while pos < len {
+        let n = file.read_at(&mut buf, pos as u64)?;
+        if n == 0 {
+            break;
+        }
+        let start = records::next_record_start(&buf[..n], 1);
+        if buf[start - 1] == b'\n' {
+            return Ok(pos + start);
+        }
+        pos += n;
+    }
+    Ok(len)
+}
+
+/// Offset of the last record starting at or before `offset`, as
+/// [`records::prev_record_start`] finds in memory, so the file up to it
+/// holds only complete records.
+fn prev_record_start(file: &File, offset: usize) -> io::Result<usize> {
+    let mut end = offset;
+    let mut buf = [0; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(buf.len());
+        let block = &mut buf[..end - start];
+        file.read_exact_at(block, start as u64)?;
+        match records::prev_record_start(block, block.len()) {
+            0 => end = start,
+            found => return Ok(start + found),
+        }
+    }
+    Ok(0)
+}
+
+/// Read `region` in blocks and queue it as chunks of complete records, until
+/// any `--timeout` deadline passes. Fails if a record runs past
+/// `--max-record-len`, rather than carrying ever more of it from block to
+/// block.
+fn read_region(file: &File, region: Range<usize>, block_size: usize, sender: SyncSender<Chunk>, config: &Config,
+               deadline: Option<&Deadline>) -> Result<()> {
+    let mut pos = region.start;
+    let mut carry = Vec::new();
+    while pos < region.end && !deadline.is_some_and(Deadline::expired) {
+        // Start fetching the next block while this one is read and parsed
+        let next = (pos + block_size).min(region.end);
+        advise(file, next..(next + block_size).min(region.end), Advice::WillNeed);
+
+        let offset = pos - carry.len();
+        let mut data = std::mem::take(&mut carry);
+        let filled = data.len();
+        let want = block_size.min(region.end - pos);
+        data.resize(filled + want, 0);
+        file.read_exact_at(&mut data[filled..], pos as u64).context("Failed to read file")?;
+        pos += want;
+
+        if pos < region.end {
+            // Hold back the trailing partial record for the next chunk
+            match records::prev_record_start(&data, data.len()) {
+                0 if data.len() > config.max_record_len => {
+                    bail!("Record too long: no newline within {} bytes of byte {}", config.max_record_len, offset)
+                }
+                0 => {
+                    carry = data;
+                    continue;
+                }
+                end => carry = data.split_off(end),
+            }
+        }
+        if sender.send(Chunk { offset, data }).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+enum Advice {
+    Sequential,
+    WillNeed,
+}
+
+/// Best-effort readahead hint for `range` of the file, where `0..0` means the
+/// whole file. Failures are ignored, as the reads work without it.
+///
+/// This only shapes how the kernel fills the page cache for `read` calls.
+/// The mmap backend reads through page faults instead, where readahead is
+/// governed per mapping by `madvise` rather than per file descriptor.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn advise(file: &File, range: Range<usize>, advice: Advice) {
+    use std::os::fd::AsRawFd;
+
+    let advice = match advice {
+        Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+        Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+    };
+    unsafe {
+        libc::posix_fadvise(file.as_raw_fd(), range.start as libc::off_t, range.len() as libc::off_t, advice);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn advise(_file: &File, _range: Range<usize>, _advice: Advice) {}
+
+/// A worker's stations, stats, skipped lines and CPU time.
+type WorkerResult = (StationMap<Vec<u8>>, ThreadStats, Skipped, Option<Duration>);
+
+fn run_worker(thread: usize, receiver: &Mutex<Receiver<Chunk>>, config: &Config, context: &RunContext)
+              -> Result<WorkerResult> {
+    let mut stations = StationMap::with_hasher(StationHasher::from_config(config));
+    let mut skipped = Skipped::default();
+    let mut stats = ThreadStats::new(thread);
+    let mut result = Ok(());
+    loop {
+        let Ok(chunk) = receiver.lock().unwrap().recv() else {
+            break;
+        };
+        // After a failure keep draining the queue so readers never block
+        if result.is_err() {
+            continue;
+        }
+        match run_partition(&chunk.data, chunk.offset, config, context) {
+            Ok((partial, readings, chunk_skipped)) => {
+                merge_partial(&mut stations, partial);
+                skipped.merge(&chunk_skipped);
+                stats.add_chunk(chunk.offset, chunk.data.len(), readings);
+            }
+            Err(err) => result = Err(err),
+        }
+    }
+    stats.longest_name = longest_name(stations.keys());
+    result.map(|()| (stations, stats, skipped, thread_cpu_time()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aggregate, partition_slice};
+
+    #[test]
+    fn test_stream_matches_mmap_backend() {
+        let names = ["Hamburg", "Bulawayo", "Palembang", "St. John's", "Cracow"];
+        let contents: String = (0..500).map(|i| format!("{};{}.{}\n", names[i % 5], (i % 97) as i32 - 40, i % 10)).collect();
+        let path = std::env::temp_dir().join(format!("rs-1brc-{}-stream", std::process::id()));
+        std::fs::write(&path, &contents).unwrap();
+        let file = File::open(&path).unwrap();
+
+        let expected = aggregate(&partition_slice(contents.as_bytes(), 1), &Config::default()).unwrap();
+        for io_threads in 1..=3 {
+            for threads in [1, 4] {
+                // Small blocks so records straddle reads and regions
+                for block_size in [7, 64, 1 << 20] {
+                    let config = Config { io_threads, threads, ..Config::default() };
+                    let actual = aggregate_stream_with(&file, &config, &RunContext::default(), block_size).unwrap();
+                    assert_eq!(actual.stations, expected.stations, "{} {} {}", io_threads, threads, block_size);
+                    assert_eq!(actual.readings(), 500);
+                    assert_eq!(actual.threads.iter().map(|t| t.bytes).sum::<usize>(), contents.len());
+                    if cfg!(unix) {
+                        assert_eq!(actual.cpu_times.len(), threads);
+                    }
+                }
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_record_starts_match_memory() {
+        // A record longer than the search buffer, and a final one without a
+        // newline
+        let contents = format!("A;1.0\n{};2.0\nB;3.0\nC;4.5", "x".repeat(5000));
+        let path = std::env::temp_dir().join(format!("rs-1brc-{}-stream-starts", std::process::id()));
+        std::fs::write(&path, &contents).unwrap();
+        let (file, data, len) = (File::open(&path).unwrap(), contents.as_bytes(), contents.len());
+        for offset in 0..=len + 1 {
+            assert_eq!(next_record_start(&file, offset, len).unwrap(), records::next_record_start(data, offset), "{}", offset);
+            if offset <= len {
+                assert_eq!(prev_record_start(&file, offset).unwrap(), records::prev_record_start(data, offset), "{}", offset);
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_block_size_within_memory_limit() {
+        let config = Config { threads: 2, io_threads: 1, ..Config::default() };
+        assert_eq!(block_size_within(usize::MAX, &config).unwrap(), BLOCK_SIZE);
+        assert_eq!(block_size_within(70_000, &config).unwrap(), 10_000);
+        let err = block_size_within(20_000, &config).unwrap_err();
+        assert_eq!(err.to_string(), "Memory limit of 20000 bytes is too small: 2 threads and 1 readers need at least 28672 bytes");
+    }
+
+    #[test]
+    fn test_stream_bounds_a_record_without_newline() {
+        let path = std::env::temp_dir().join(format!("rs-1brc-{}-stream-long", std::process::id()));
+        std::fs::write(&path, format!("A;1.0\n{};2.0\n", "x".repeat(20_000))).unwrap();
+        let file = File::open(&path).unwrap();
+        // 4096-byte blocks, so the long record is carried over a few reads
+        let config = Config { threads: 1, io_threads: 1, memory_limit: Some(16_384), max_record_len: 10_000,
+                              ..Config::default() };
+        let err = aggregate_stream(&file, &config, &RunContext::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Record too long: no newline within 10000 bytes of byte 6");
+        assert_eq!(aggregate_stream(&file, &Config { max_record_len: 30_000, ..config }, &RunContext::default()).unwrap().readings(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_stream_reports_malformed_record_offset() {
+        let path = std::env::temp_dir().join(format!("rs-1brc-{}-stream-malformed", std::process::id()));
+        std::fs::write(&path, "A;1.0\nB;2.0\nbad\nC;3.0\n").unwrap();
+        let file = File::open(&path).unwrap();
+        let config = Config { threads: 2, ..Config::default() };
+        let err = aggregate_stream_with(&file, &config, &RunContext::default(), 4).unwrap_err();
+        assert_eq!(err.to_string(), "Malformed record at byte 12: \"bad\"");
+        std::fs::remove_file(path).unwrap();
+    }
+}
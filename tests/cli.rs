@@ -0,0 +1,134 @@
+//! End-to-end tests of the built binary: argument parsing, output and exit
+//! codes, against the measurements in `tests/fixtures`.
+
+use assert_cmd::{cargo::cargo_bin_cmd, Command};
+use std::path::{Path, PathBuf};
+
+const VERBOSE: &str = "\
+Bulawayo: min=-8.9 max=8.9 mean=0.0 count=2
+Cracow: min=12.6 max=12.6 mean=12.6 count=1
+Hamburg: min=12 max=34.2 mean=23.1 count=2
+Palembang: min=38.8 max=38.8 mean=38.8 count=1
+St. John's: min=15.2 max=15.2 mean=15.2 count=1
+";
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn measurements() -> String {
+    fixtures().join("data/measurements.txt").to_str().unwrap().to_string()
+}
+
+fn rs_1brc() -> Command {
+    let mut cmd = cargo_bin_cmd!("rs-1brc");
+    // Keep error output to the message, whatever the caller's environment
+    cmd.env_remove("RUST_BACKTRACE").env_remove("RUST_LIB_BACKTRACE");
+    cmd
+}
+
+#[test]
+fn test_default_invocation() {
+    // With no arguments the input is data/measurements.txt
+    rs_1brc().current_dir(fixtures()).assert().success().stdout(VERBOSE).stderr("");
+}
+
+#[test]
+fn test_thread_counts_agree() {
+    for threads in ["1", "2", "7"] {
+        rs_1brc().args([&measurements(), "--threads", threads]).assert().success().stdout(VERBOSE);
+    }
+}
+
+#[test]
+fn test_formats() {
+    rs_1brc()
+        .args([&measurements(), "--format", "official"])
+        .assert()
+        .success()
+        .stdout("{Bulawayo=-8.9/0.0/8.9, Cracow=12.6/12.6/12.6, Hamburg=12.0/23.1/34.2, Palembang=38.8/38.8/38.8, \
+                 St. John's=15.2/15.2/15.2}\n");
+    rs_1brc()
+        .args([&measurements(), "--format", "csv"])
+        .assert()
+        .success()
+        .stdout("station,min,mean,max,count\nBulawayo,-8.9,0.0,8.9,2\nCracow,12.6,12.6,12.6,1\nHamburg,12.0,23.1,34.2,2\n\
+                 Palembang,38.8,38.8,38.8,1\nSt. John's,15.2,15.2,15.2,1\n");
+}
+
+#[test]
+fn test_output_file() {
+    let output = std::env::temp_dir().join(format!("rs-1brc-cli-{}-output", std::process::id()));
+    rs_1brc()
+        .args([&measurements(), "--output", output.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), VERBOSE);
+    std::fs::remove_file(output).unwrap();
+}
+
+#[test]
+fn test_missing_file() {
+    let assert = rs_1brc().arg("missing.txt").current_dir(fixtures()).assert().failure().code(1).stdout("");
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.starts_with("Error: Failed to open file: missing.txt\n"), "{}", stderr);
+}
+
+#[test]
+fn test_invalid_arguments() {
+    // Usage errors exit with 2, before any input is read
+    for args in [&["--threads", "0"][..], &["--no-such-flag"], &["--format", "yaml"]] {
+        let assert = rs_1brc().args(args).arg("missing.txt").assert().failure().code(2).stdout("");
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+        assert!(stderr.starts_with("error: "), "{:?}: {}", args, stderr);
+    }
+}
+
+#[test]
+fn test_timeout() {
+    rs_1brc().args([&measurements(), "--timeout", "3600"]).assert().success().stdout(VERBOSE).stderr("");
+    // Already expired when the workers start, so nothing is read, but the
+    // run still succeeds with a note
+    rs_1brc()
+        .args([&measurements(), "--timeout", "0"])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("Timed out after 0ns: results cover only part of the input, so counts are incomplete\n");
+    rs_1brc().args([&measurements(), "--timeout", "-1"]).assert().failure().code(2);
+}
+
+#[test]
+fn test_checkpoint_then_resume() {
+    let checkpoint = std::env::temp_dir().join(format!("rs-1brc-cli-{}-checkpoint", std::process::id()));
+    let checkpoint = checkpoint.to_str().unwrap();
+    rs_1brc()
+        .args([&measurements(), "--checkpoint-interval", "0", "--checkpoint-file", checkpoint])
+        .assert()
+        .success()
+        .stdout(VERBOSE);
+    // The last checkpoint covers the whole file, so resuming adds nothing
+    rs_1brc().args([&measurements(), "--resume", checkpoint]).assert().success().stdout(VERBOSE);
+    std::fs::remove_file(checkpoint).unwrap();
+}
+
+#[test]
+fn test_compare() {
+    // Bulawayo's mean is only a rounding step apart, so isn't reported
+    rs_1brc()
+        .args(["compare", "results/before.txt", "results/after.txt"])
+        .current_dir(fixtures())
+        .assert()
+        .success()
+        .stdout("Hamburg: mean 23.1 -> 24.5 (+1.4), max 34.2 -> 35.5 (+1.3), count 2 -> 3 (+1)\n\
+                 Only in results/before.txt: Palembang\n\
+                 Only in results/after.txt: Abha\n\
+                 Stations differing: 1, only in results/before.txt: 1, only in results/after.txt: 1\n");
+    rs_1brc()
+        .args(["compare", "results/before.txt", "results/before.txt"])
+        .current_dir(fixtures())
+        .assert()
+        .success()
+        .stdout("No differences, stations compared: 5\n");
+}